@@ -1,5 +1,6 @@
 from flask import Flask, jsonify, request
 
+import hashlib
 import io
 
 import numpy as np
@@ -25,10 +26,9 @@ def tensorflow_classify(image_data):
     results = np.squeeze(output_data)
 
     top_k = results.argsort()[-5:][::-1]
-    if floating_model:
-        return {"probability": float(results[top_k[0]]), "label": labels[top_k[0]]}
-    else:
-        return {"probability": float(results[top_k[0]] / 255.0), "label": labels[top_k[0]]}
+    scale = 1.0 if floating_model else 255.0
+    scores = [{"probability": float(results[i] / scale), "label": labels[i]} for i in top_k]
+    return {"probability": scores[0]["probability"], "label": scores[0]["label"], "scores": scores, "model": model_version}
 
 
 def load_labels(filename):
@@ -36,6 +36,11 @@ def load_labels(filename):
         return [line.strip() for line in f.readlines()]
 
 
+def file_hash(filename):
+    with open(filename, 'rb') as f:
+        return hashlib.sha256(f.read()).hexdigest()
+
+
 app = Flask(__name__)
 
 
@@ -44,6 +49,11 @@ def classify():
     return jsonify(tensorflow_classify(request.get_data()))
 
 
+@app.route('/model', methods=['GET'])
+def model():
+    return jsonify({"model": model_version})
+
+
 if __name__ == '__main__':
     interpreter = tflite.Interpreter(
         model_path="./comic_net.tflite", num_threads=None)
@@ -60,5 +70,7 @@ if __name__ == '__main__':
     width = input_details[0]['shape'][2]
 
     labels = load_labels("./comic_net.labels")
+    # Changes, whenever the model or its labels are replaced
+    model_version = file_hash("./comic_net.tflite")[:16] + file_hash("./comic_net.labels")[:16]
 
     app.run(debug=False, host='0.0.0.0')
@@ -58,7 +58,9 @@ void setup()
     ESP.restart();
   }
 
-  size_t received = http_request("http://192.168.178.3:8000/comic/inkplate", buffer, buffer_size);
+  // Every frame keeps its own history of shown comics on the server
+  String url = "http://192.168.178.3:8000/comic/inkplate?device=" + WiFi.macAddress();
+  size_t received = http_request((char *)url.c_str(), buffer, buffer_size);
 
   log_d("Received bytes %d, expected %d", received, buffer_size - 1);
 
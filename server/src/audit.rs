@@ -0,0 +1,78 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::filter::{Decision, Verdict};
+use crate::{CONFIG, STORE};
+
+/// Entry of the filter decision log, describing why an image of a strip has
+/// been accepted or rejected.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DecisionRecord {
+  /// Assigned by the comic store, increasing over time
+  pub id: u64,
+  pub timestamp: DateTime<Utc>,
+  pub source: String,
+  pub strip_id: u64,
+  pub url: String,
+  /// Blob hash of the judged image
+  #[serde(default)]
+  pub hash: Option<String>,
+  #[serde(flatten)]
+  pub decision: Decision,
+}
+
+/// Conditions for the records to list. Unset conditions match everything.
+#[derive(Debug, Default)]
+pub struct DecisionQuery {
+  pub source: Option<String>,
+  pub strip_id: Option<u64>,
+  /// Name of the first measure, which is the best scoring label for the
+  /// classifiers
+  pub label: Option<String>,
+  pub verdict: Option<Verdict>,
+}
+
+impl DecisionQuery {
+  pub fn matches(&self, record: &DecisionRecord) -> bool {
+    self
+      .source
+      .as_ref()
+      .is_none_or(|source| *source == record.source)
+      && self.strip_id.is_none_or(|id| id == record.strip_id)
+      && self.label.as_ref().is_none_or(|label| {
+        record
+          .decision
+          .measures
+          .first()
+          .is_some_and(|measure| measure.name == *label)
+      })
+      && self
+        .verdict
+        .is_none_or(|verdict| verdict == record.decision.verdict)
+  }
+}
+
+/// Add a decision to the log. Records older than the configured retention
+/// are dropped along the way.
+pub fn record_decision(
+  source: &str,
+  strip_id: u64,
+  url: &str,
+  hash: Option<&str>,
+  decision: &Decision,
+) {
+  let record = DecisionRecord {
+    id: 0,
+    timestamp: Utc::now(),
+    source: source.to_string(),
+    strip_id,
+    url: url.to_string(),
+    hash: hash.map(str::to_string),
+    decision: decision.clone(),
+  };
+  let retention = Duration::days(CONFIG.get().decision_log_days);
+
+  if let Err(error) = STORE.get().save_decision(record, retention) {
+    println!("Failed to record filter decision: {}", error);
+  }
+}
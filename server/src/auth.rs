@@ -0,0 +1,46 @@
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request};
+
+use crate::CONFIG;
+
+// Compare the `Authorization: Bearer <token>` header against the given token.
+// Without a configured token, the guarded routes are disabled altogether.
+fn bearer_outcome<T>(
+  request: &Request<'_>,
+  token: &Option<String>,
+  guard: T,
+) -> request::Outcome<T, ()> {
+  let expected = match token {
+    Some(ref token) => format!("Bearer {}", token),
+    None => return request::Outcome::Failure((Status::Forbidden, ())),
+  };
+
+  match request.headers().get_one("Authorization") {
+    Some(header) if header == expected => request::Outcome::Success(guard),
+    _ => request::Outcome::Failure((Status::Unauthorized, ())),
+  }
+}
+
+/// Request guard ensuring the configured upload token is presented.
+pub struct UploadToken;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for UploadToken {
+  type Error = ();
+
+  async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+    bearer_outcome(request, &CONFIG.get().upload_token, UploadToken)
+  }
+}
+
+/// Request guard ensuring the configured admin token is presented.
+pub struct AdminToken;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminToken {
+  type Error = ();
+
+  async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+    bearer_outcome(request, &CONFIG.get().admin_token, AdminToken)
+  }
+}
@@ -0,0 +1,385 @@
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use crate::BLOBS;
+
+#[derive(Debug)]
+struct BlobEntry {
+  size: u64,
+  last_access: SystemTime,
+  references: usize,
+  pinned: bool,
+}
+
+/// Content addressed storage of downloaded image data on disk.
+///
+/// Blobs are identified by the SHA-256 of their content. Once the configured
+/// size is exceeded, the least recently used blobs are evicted. Blobs
+/// referenced by a living `BlobHandle` or pinned are never evicted.
+#[derive(Debug)]
+pub struct BlobCache {
+  path: PathBuf,
+  max_size: u64,
+  entries: Mutex<HashMap<String, BlobEntry>>,
+}
+
+/// Reference to a blob within the global blob cache, which protects it from
+/// being evicted.
+#[derive(Debug)]
+pub struct BlobHandle {
+  hash: String,
+}
+
+impl BlobHandle {
+  pub fn hash(&self) -> &str {
+    &self.hash
+  }
+
+  pub fn read(&self) -> io::Result<Vec<u8>> {
+    BLOBS.get().read(&self.hash)
+  }
+
+  pub fn size(&self) -> u64 {
+    BLOBS.get().size(&self.hash).unwrap_or(0)
+  }
+}
+
+impl Clone for BlobHandle {
+  fn clone(&self) -> Self {
+    BLOBS.get().acquire(&self.hash);
+    BlobHandle {
+      hash: self.hash.clone(),
+    }
+  }
+}
+
+impl Drop for BlobHandle {
+  fn drop(&mut self) {
+    BLOBS.get().release(&self.hash);
+  }
+}
+
+/// Hex encoded SHA-256 of the given data
+pub fn content_hash(data: &[u8]) -> String {
+  format!("{:x}", Sha256::digest(data))
+}
+
+impl BlobCache {
+  pub fn open(path: PathBuf, max_size: u64) -> io::Result<Self> {
+    fs::create_dir_all(&path)?;
+
+    let mut entries = HashMap::new();
+    for bucket in fs::read_dir(&path)? {
+      let bucket = bucket?.path();
+      if !bucket.is_dir() {
+        continue;
+      }
+      for blob in fs::read_dir(&bucket)? {
+        let blob = blob?;
+        let metadata = blob.metadata()?;
+        if let Some(hash) = blob.file_name().to_str() {
+          entries.insert(
+            hash.to_string(),
+            BlobEntry {
+              size: metadata.len(),
+              last_access: metadata.modified()?,
+              references: 0,
+              pinned: false,
+            },
+          );
+        }
+      }
+    }
+
+    println!("Blob cache contains {} entries", entries.len());
+
+    Ok(BlobCache {
+      path,
+      max_size,
+      entries: Mutex::new(entries),
+    })
+  }
+
+  fn blob_path(&self, hash: &str) -> PathBuf {
+    self.path.join(&hash[..2]).join(hash)
+  }
+
+  /// Add the given data to the cache and return a handle to it.
+  pub fn insert(&self, data: &[u8]) -> io::Result<BlobHandle> {
+    let hash = content_hash(data);
+    let path = self.blob_path(&hash);
+
+    {
+      let mut entries = self.entries.lock().unwrap();
+      let entry = entries.entry(hash.clone()).or_insert(BlobEntry {
+        size: data.len() as u64,
+        last_access: SystemTime::now(),
+        references: 0,
+        pinned: false,
+      });
+      entry.last_access = SystemTime::now();
+      entry.references += 1;
+    }
+
+    if !path.exists() {
+      if let Err(error) = write_atomically(&path, data) {
+        self.release(&hash);
+        return Err(error);
+      }
+    }
+
+    self.evict();
+
+    Ok(BlobHandle { hash })
+  }
+
+  /// Handle to an already stored blob, if it is still available.
+  pub fn handle(&self, hash: &str) -> Option<BlobHandle> {
+    let mut entries = self.entries.lock().unwrap();
+    let entry = entries.get_mut(hash)?;
+    entry.references += 1;
+
+    Some(BlobHandle {
+      hash: hash.to_string(),
+    })
+  }
+
+  /// Keep the blob, regardless of its use, until it is unpinned again. Pins
+  /// are not persisted. Returns whether the blob is available at all.
+  pub fn pin(&self, hash: &str) -> bool {
+    match self.entries.lock().unwrap().get_mut(hash) {
+      Some(entry) => {
+        entry.pinned = true;
+        true
+      }
+      None => false,
+    }
+  }
+
+  pub fn unpin(&self, hash: &str) {
+    if let Some(entry) = self.entries.lock().unwrap().get_mut(hash) {
+      entry.pinned = false;
+    }
+    self.evict();
+  }
+
+  fn read(&self, hash: &str) -> io::Result<Vec<u8>> {
+    let path = self.blob_path(hash);
+    let data = fs::read(&path)?;
+
+    let now = SystemTime::now();
+    if let Some(entry) = self.entries.lock().unwrap().get_mut(hash) {
+      entry.last_access = now;
+    }
+    // Keep the access time across restarts. Failing to do so only affects
+    // the eviction order.
+    if let Ok(file) = fs::File::options().write(true).open(&path) {
+      let _ = file.set_modified(now);
+    }
+
+    Ok(data)
+  }
+
+  pub fn contains(&self, hash: &str) -> bool {
+    self.entries.lock().unwrap().contains_key(hash)
+  }
+
+  fn size(&self, hash: &str) -> Option<u64> {
+    self
+      .entries
+      .lock()
+      .unwrap()
+      .get(hash)
+      .map(|entry| entry.size)
+  }
+
+  fn acquire(&self, hash: &str) {
+    if let Some(entry) = self.entries.lock().unwrap().get_mut(hash) {
+      entry.references += 1;
+    }
+  }
+
+  fn release(&self, hash: &str) {
+    if let Some(entry) = self.entries.lock().unwrap().get_mut(hash) {
+      entry.references = entry.references.saturating_sub(1);
+    }
+  }
+
+  fn evict(&self) {
+    let mut entries = self.entries.lock().unwrap();
+    let mut total_size: u64 = entries.values().map(|entry| entry.size).sum();
+    if total_size <= self.max_size {
+      return;
+    }
+
+    let mut candidates: Vec<(String, SystemTime, u64)> = entries
+      .iter()
+      .filter(|(_, entry)| entry.references == 0 && !entry.pinned)
+      .map(|(hash, entry)| (hash.clone(), entry.last_access, entry.size))
+      .collect();
+    candidates.sort_by_key(|(_, last_access, _)| *last_access);
+
+    for (hash, _, size) in candidates {
+      if total_size <= self.max_size {
+        break;
+      }
+      match fs::remove_file(self.blob_path(&hash)) {
+        Ok(_) => {
+          entries.remove(&hash);
+          total_size -= size;
+        }
+        Err(error) => println!("Failed to evict blob {}: {}", hash, error),
+      }
+    }
+
+    if total_size > self.max_size {
+      println!(
+        "Blob cache exceeds its size limit, as {} bytes are still in use",
+        total_size
+      );
+    }
+  }
+}
+
+fn write_atomically(path: &Path, data: &[u8]) -> io::Result<()> {
+  if let Some(parent) = path.parent() {
+    fs::create_dir_all(parent)?;
+  }
+  let temporary_path = path.with_extension("tmp");
+  fs::write(&temporary_path, data)?;
+  fs::rename(&temporary_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use super::*;
+  use crate::testing;
+
+  fn cache(name: &str, max_size: u64) -> (PathBuf, BlobCache) {
+    testing::init();
+    let path = std::env::temp_dir().join(format!(
+      "twitter_comic_streamer_{}_blobs_{}",
+      std::process::id(),
+      name
+    ));
+    let _ = fs::remove_dir_all(&path);
+    let cache = BlobCache::open(path.clone(), max_size).unwrap();
+    (path, cache)
+  }
+
+  // Handles release their blob within the global cache on drop, so blobs of
+  // other caches are released by hand.
+  fn insert_unreferenced(cache: &BlobCache, data: &[u8]) -> String {
+    let handle = cache.insert(data).unwrap();
+    let hash = handle.hash().to_string();
+    cache.release(&hash);
+    std::mem::forget(handle);
+    hash
+  }
+
+  fn set_last_access(cache: &BlobCache, hash: &str, secs: u64) {
+    let last_access = SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+    cache
+      .entries
+      .lock()
+      .unwrap()
+      .get_mut(hash)
+      .unwrap()
+      .last_access = last_access;
+    fs::File::options()
+      .write(true)
+      .open(cache.blob_path(hash))
+      .unwrap()
+      .set_modified(last_access)
+      .unwrap();
+  }
+
+  #[test]
+  fn least_recently_used_blobs_are_evicted() {
+    let (_, cache) = cache("lru", 250);
+    let first = insert_unreferenced(&cache, &[1; 100]);
+    let second = insert_unreferenced(&cache, &[2; 100]);
+    set_last_access(&cache, &first, 2000);
+    set_last_access(&cache, &second, 1000);
+
+    let third = insert_unreferenced(&cache, &[3; 100]);
+    assert!(cache.contains(&first));
+    assert!(!cache.contains(&second));
+    assert!(!cache.blob_path(&second).exists());
+    assert!(cache.contains(&third));
+    assert_eq!(cache.read(&first).unwrap(), vec![1; 100]);
+  }
+
+  #[test]
+  fn referenced_blobs_are_never_evicted() {
+    let (_, cache) = cache("referenced", 150);
+    let handle = cache.insert(&[1; 100]).unwrap();
+    let referenced = handle.hash().to_string();
+    set_last_access(&cache, &referenced, 1000);
+
+    let second = insert_unreferenced(&cache, &[2; 100]);
+    set_last_access(&cache, &second, 2000);
+    let third = insert_unreferenced(&cache, &[3; 100]);
+    assert!(cache.contains(&referenced));
+    assert!(!cache.contains(&second));
+    assert!(cache.contains(&third));
+
+    // The limit is exceeded rather than evicting any referenced blob
+    let fourth = cache.insert(&[4; 100]).unwrap();
+    assert!(cache.contains(&referenced));
+    assert!(cache.contains(fourth.hash()));
+    assert!(!cache.contains(&third));
+    std::mem::forget(handle);
+    std::mem::forget(fourth);
+  }
+
+  #[test]
+  fn pinned_blobs_are_never_evicted() {
+    let (_, cache) = cache("pinned", 150);
+    let pinned = insert_unreferenced(&cache, &[1; 100]);
+    assert!(cache.pin(&pinned));
+    set_last_access(&cache, &pinned, 1000);
+
+    let second = insert_unreferenced(&cache, &[2; 100]);
+    set_last_access(&cache, &second, 2000);
+    let third = insert_unreferenced(&cache, &[3; 100]);
+    assert!(cache.contains(&pinned));
+    assert!(!cache.contains(&second));
+    assert!(cache.contains(&third));
+
+    // Once unpinned, the blob is evicted as the least recently used one
+    cache.unpin(&pinned);
+    assert!(!cache.contains(&pinned));
+    assert!(!cache.pin(&pinned));
+  }
+
+  #[test]
+  fn reopened_cache_keeps_its_blobs_and_their_order() {
+    let (path, cache) = cache("reopened", 250);
+    let first = insert_unreferenced(&cache, &[1; 100]);
+    let second = insert_unreferenced(&cache, &[2; 100]);
+    set_last_access(&cache, &first, 2000);
+    set_last_access(&cache, &second, 1000);
+    drop(cache);
+
+    let cache = BlobCache::open(path, 250).unwrap();
+    assert!(cache.contains(&first) && cache.contains(&second));
+    assert_eq!(cache.size(&first), Some(100));
+
+    // The access times are restored from the blob files
+    insert_unreferenced(&cache, &[3; 100]);
+    assert!(cache.contains(&first));
+    assert!(!cache.contains(&second));
+
+    let handle = cache.handle(&first).unwrap();
+    assert_eq!(cache.read(handle.hash()).unwrap(), vec![1; 100]);
+    std::mem::forget(handle);
+  }
+}
@@ -0,0 +1,141 @@
+use async_trait::async_trait;
+use image::imageops::FilterType;
+use image::DynamicImage;
+use std::fs;
+use std::io::Cursor;
+use std::sync::Arc;
+use tract_onnx::prelude::*;
+
+use crate::blob_cache::content_hash;
+use crate::comic_image::ComicImage;
+use crate::filter::{label_measures, ClassificationRules, Decision, Filter, LabelScore, Verdict};
+
+type ClassifierModel = TypedRunnableModel<TypedModel>;
+
+/// Classifier running the ONNX export of the `comic_net` in process, instead
+/// of asking the python classifier service.
+pub struct EmbeddedClassifierFilter {
+  model: Arc<ClassifierModel>,
+  model_hash: String,
+  labels: Vec<String>,
+  input_size: u32,
+  rules: ClassificationRules,
+}
+
+impl EmbeddedClassifierFilter {
+  pub fn load(
+    model_path: &str,
+    labels_path: &str,
+    input_size: u32,
+    rules: ClassificationRules,
+  ) -> TractResult<Self> {
+    let data = fs::read(model_path)?;
+    let model_hash = content_hash(&data);
+
+    let size = input_size as usize;
+    let model = tract_onnx::onnx()
+      .model_for_read(&mut Cursor::new(data))?
+      .with_input_fact(0, f32::fact([1, size, size, 3]).into())?
+      .into_optimized()?
+      .into_runnable()?;
+
+    let labels = fs::read_to_string(labels_path)?
+      .lines()
+      .map(|label| label.trim().to_string())
+      .filter(|label| !label.is_empty())
+      .collect();
+
+    Ok(EmbeddedClassifierFilter {
+      model: Arc::new(model),
+      model_hash,
+      labels,
+      input_size,
+      rules,
+    })
+  }
+}
+
+// Mirrors the preprocessing of `tensorflow_classify` within the classifier
+// service: The image is resized to the input size and pasted onto a white RGB
+// canvas. As the paste does not use a mask, transparency is simply dropped.
+fn input_tensor(image: &DynamicImage, input_size: u32) -> Tensor {
+  let resized = image
+    .resize_exact(input_size, input_size, FilterType::CatmullRom)
+    .to_rgb8();
+
+  let size = input_size as usize;
+  tract_ndarray::Array4::from_shape_fn((1, size, size, 3), |(_, y, x, c)| {
+    (resized.get_pixel(x as u32, y as u32)[c] as f32 - 127.5) / 127.5
+  })
+  .into()
+}
+
+fn classify(
+  model: &ClassifierModel,
+  labels: &[String],
+  input: Tensor,
+) -> TractResult<Vec<LabelScore>> {
+  let outputs = model.run(tvec!(input.into()))?;
+  let probabilities = outputs[0].to_array_view::<f32>()?;
+
+  let mut scores: Vec<LabelScore> = probabilities
+    .iter()
+    .zip(labels.iter())
+    .map(|(probability, label)| LabelScore {
+      probability: *probability as f64,
+      label: label.clone(),
+    })
+    .collect();
+  scores.sort_by(|a, b| b.probability.total_cmp(&a.probability));
+
+  Ok(scores)
+}
+
+#[async_trait]
+impl Filter for EmbeddedClassifierFilter {
+  async fn decision(&self, image: Arc<ComicImage>) -> Decision {
+    // Decoding and inference block for a noticeable amount of time
+    let model = self.model.clone();
+    let labels = self.labels.clone();
+    let input_size = self.input_size;
+    let scores = tokio::task::spawn_blocking(move || {
+      image
+        .dynamic_image()
+        .map(|decoded| classify(&model, &labels, input_tensor(&decoded, input_size)))
+    })
+    .await;
+
+    match scores {
+      Ok(Ok(Ok(scores))) => {
+        for score in scores.iter() {
+          println!("    ?   {}: {}", score.label, score.probability);
+        }
+        let verdict = Verdict::from(self.rules.is_accepted(&scores));
+        println!("    ? {}", verdict);
+        Decision::new(self.identity(), verdict, label_measures(&scores))
+      }
+      // Failing inference says nothing about the image itself, so it is
+      // classified again later on.
+      Ok(Ok(Err(error))) => {
+        println!("    ? Classification failed: {}", error);
+        Decision::new(self.identity(), Verdict::Undecided, vec![])
+      }
+      Ok(Err(error)) => {
+        println!("    ? Unreadable image: {}", error);
+        Decision::new(self.identity(), Verdict::from(&error), vec![])
+      }
+      // The classification task itself did not finish
+      Err(error) => {
+        println!("    ? Classification failed: {}", error);
+        Decision::new(self.identity(), Verdict::Undecided, vec![])
+      }
+    }
+  }
+
+  fn identity(&self) -> String {
+    format!(
+      "embedded_classifier({}, {}, {:?}, {:?})",
+      self.model_hash, self.input_size, self.labels, self.rules
+    )
+  }
+}
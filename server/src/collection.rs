@@ -0,0 +1,975 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Mutex, Notify};
+use tokio::time::sleep;
+
+use crate::audit::record_decision;
+use crate::comic_image::ComicImage;
+use crate::filter::{Decision, Filter, ImageFilter, Verdict};
+use crate::moderation::Moderation;
+use crate::source::{ComicSource, Popularity, Source, SourceError, SourceStrip};
+use crate::{CONFIG, STORE};
+
+const WATCH_SETTLE_TIME: u64 = 2;
+const REFRESH_ATTEMPTS: u32 = 4;
+const REFRESH_RETRY_DELAY: u64 = 15;
+const POPULARITY_REFRESH_HOURS: i64 = 6;
+
+#[derive(Debug, Clone)]
+pub struct Comic {
+  pub url: String,
+  image: Arc<ComicImage>,
+}
+
+impl Comic {
+  pub fn new(url: String, image: Arc<ComicImage>) -> Self {
+    Comic { url, image }
+  }
+
+  pub fn image(&self) -> Arc<ComicImage> {
+    self.image.clone()
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct ComicStrip {
+  pub id: u64,
+  pub comics: Vec<Comic>,
+  pub created_at: DateTime<Utc>,
+  pub author: Option<String>,
+  pub title: Option<String>,
+  pub popularity: Option<Popularity>,
+}
+
+/// Strip, of which at least one image could not be judged by the filter yet.
+/// It is filtered again on later refreshes, until the maximum amount of
+/// attempts is reached.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingStrip {
+  pub id: u64,
+  pub created_at: DateTime<Utc>,
+  pub image_urls: Vec<String>,
+  pub attempts: u32,
+  #[serde(default)]
+  pub popularity: Option<Popularity>,
+}
+
+#[derive(Clone)]
+pub struct UserComicCollection<S = Source> {
+  pub source: S,
+  pub comic_strips: Vec<Arc<ComicStrip>>,
+  pub pending: Vec<PendingStrip>,
+  max_id: Option<u64>,
+  pub max_amount: usize,
+  popularity_refreshed_at: Option<DateTime<Utc>>,
+}
+
+async fn refresh_user_comic_collection<S>(
+  collection: &UserComicCollection<S>,
+  filter: &ImageFilter,
+) -> Result<UserComicCollection<S>, SourceError>
+where
+  S: ComicSource + Clone,
+{
+  let batch = collection.source.fetch_strips(collection.max_id).await?;
+  let identifier = collection.source.identifier();
+
+  let mut comic_strips = collection.comic_strips.clone();
+  let mut pending_strips = collection.pending.clone();
+  if batch.exhaustive {
+    let available_ids: Vec<u64> = batch.strips.iter().map(|strip| strip.id).collect();
+    comic_strips.retain(|strip| available_ids.contains(&strip.id));
+    pending_strips.retain(|strip| available_ids.contains(&strip.id));
+    for rejected in STORE.get().load_rejected(&identifier).unwrap_or_default() {
+      if !available_ids.contains(&rejected.id) {
+        log_store_error(STORE.get().remove_rejected(&identifier, rejected.id));
+      }
+    }
+  }
+  let mut ids: Vec<u64> = comic_strips.iter().map(|comic| comic.id).collect();
+  ids.extend(pending_strips.iter().map(|strip| strip.id));
+
+  let moderation = Moderation::load();
+  let max_attempts = CONFIG.get().pending_max_attempts;
+  let mut pending = vec![];
+  for mut strip in pending_strips {
+    if moderation.is_hidden(&identifier, strip.id) {
+      println!("Dropping hidden pending strip {}", strip.id);
+      continue;
+    }
+    strip.attempts += 1;
+    let comics = filter_strip(
+      &collection.source,
+      filter,
+      &moderation,
+      strip.id,
+      &strip.image_urls,
+      &[],
+    )
+    .await;
+    match comics {
+      Some(comics) => {
+        let source_strip = SourceStrip {
+          id: strip.id,
+          created_at: strip.created_at,
+          image_urls: strip.image_urls,
+          popularity: strip.popularity,
+        };
+        remember_rejections(&identifier, &source_strip, comics.len());
+        push_strip(&mut comic_strips, &source_strip, comics)
+      }
+      None if strip.attempts < max_attempts => {
+        println!(
+          "Strip {} is still pending after {} attempts",
+          strip.id, strip.attempts
+        );
+        pending.push(strip);
+      }
+      None => println!(
+        "Giving up on strip {} after {} attempts",
+        strip.id, strip.attempts
+      ),
+    }
+  }
+
+  for strip in batch.strips {
+    if ids.contains(&strip.id) {
+      if let Some(popularity) = strip.popularity {
+        update_popularity(&mut comic_strips, strip.id, popularity);
+      }
+      continue;
+    }
+    if moderation.is_hidden(&identifier, strip.id) {
+      println!("Skipping hidden strip {}", strip.id);
+      ids.push(strip.id);
+      continue;
+    }
+
+    let comics = filter_strip(
+      &collection.source,
+      filter,
+      &moderation,
+      strip.id,
+      &strip.image_urls,
+      &[],
+    )
+    .await;
+    match comics {
+      Some(comics) => {
+        remember_rejections(&identifier, &strip, comics.len());
+        push_strip(&mut comic_strips, &strip, comics)
+      }
+      None => {
+        println!("Strip {} is pending", strip.id);
+        pending.push(PendingStrip {
+          id: strip.id,
+          created_at: strip.created_at,
+          image_urls: strip.image_urls,
+          attempts: 1,
+          popularity: strip.popularity,
+        });
+      }
+    }
+
+    // Mark strip as being processed.
+    ids.push(strip.id);
+  }
+
+  let new_max_id = match batch.max_id {
+    Some(max_id) => Some(max_id),
+    None => collection.max_id,
+  };
+
+  let mut popularity_refreshed_at = collection.popularity_refreshed_at;
+  let is_popularity_outdated = popularity_refreshed_at.is_none_or(|refreshed_at| {
+    Utc::now() - refreshed_at >= chrono::Duration::hours(POPULARITY_REFRESH_HOURS)
+  });
+  if is_popularity_outdated && refresh_popularity(&collection.source, &mut comic_strips).await {
+    popularity_refreshed_at = Some(Utc::now());
+  }
+
+  let mut refreshed = UserComicCollection {
+    source: collection.source.clone(),
+    max_id: new_max_id,
+    max_amount: collection.max_amount,
+    comic_strips,
+    pending,
+    popularity_refreshed_at,
+  };
+  apply_collection_constraints(&mut refreshed);
+  refreshed.persist_changes(&collection.comic_strips);
+
+  Ok(refreshed)
+}
+
+// Comics of the strip accepted by the filter. As strips are only added as a
+// whole, `None` is returned as soon as any image can not be judged yet.
+// Manually included strips bypass the filter and earlier classifications.
+// Images of already `known` comics are not loaded again.
+async fn filter_strip<S: ComicSource>(
+  source: &S,
+  filter: &ImageFilter,
+  moderation: &Moderation,
+  id: u64,
+  image_urls: &[String],
+  known: &[Comic],
+) -> Option<Vec<Comic>> {
+  let identifier = source.identifier();
+  let is_included = moderation.is_included(&identifier, id);
+  let mut comics: Vec<Comic> = vec![];
+  for url in image_urls {
+    let stored_classification = match is_included {
+      true => None,
+      false => STORE.get().classification(id, url).unwrap_or(None),
+    };
+    if stored_classification == Some(false) {
+      // Rejected before, no need to download it again.
+      continue;
+    }
+
+    let known_image = known
+      .iter()
+      .find(|comic| comic.url == *url)
+      .map(|comic| comic.image());
+    let image = match known_image {
+      Some(image) => Ok(image),
+      None => load_comic_image(source, url).await,
+    };
+    let image = match image {
+      Ok(image) => image,
+      Err(error) if error.is_transient() => {
+        println!("Could not load image {}: {}", url, error);
+        return None;
+      }
+      Err(error) => {
+        println!("Skipping image {}: {}", url, error);
+        continue;
+      }
+    };
+    if moderation.is_blocked(image.blob_hash()) {
+      println!("Skipping blocked image {}", url);
+      let decision = Decision::new("moderation(blocked)".to_string(), Verdict::Reject, vec![]);
+      record_decision(&identifier, id, url, image.blob_hash(), &decision);
+      continue;
+    }
+
+    // Reused classifications have been recorded when they were made
+    let verdict = match stored_classification {
+      Some(is_valid) => Verdict::from(is_valid),
+      None if is_included => {
+        let decision = Decision::new("moderation(included)".to_string(), Verdict::Accept, vec![]);
+        record_decision(&identifier, id, url, image.blob_hash(), &decision);
+        Verdict::Accept
+      }
+      None => {
+        let decision = filter.decision(image.clone()).await;
+        record_decision(&identifier, id, url, image.blob_hash(), &decision);
+        if decision.verdict != Verdict::Undecided {
+          let is_valid = decision.verdict == Verdict::Accept;
+          log_store_error(STORE.get().save_classification(id, url, is_valid));
+        }
+        decision.verdict
+      }
+    };
+
+    match verdict {
+      Verdict::Accept => comics.push(Comic::new(url.clone(), image)),
+      Verdict::Reject => {}
+      Verdict::Undecided => return None,
+    }
+  }
+
+  Some(comics)
+}
+
+// Strips, of which images have been rejected, are kept to filter them again
+// once the filter changes.
+fn remember_rejections(identifier: &str, strip: &SourceStrip, accepted: usize) {
+  let store = STORE.get();
+  if accepted < strip.image_urls.len() {
+    log_store_error(store.save_rejected(identifier, strip));
+  } else {
+    log_store_error(store.remove_rejected(identifier, strip.id));
+  }
+}
+
+// Filter the strips of the collection and its rejected strips again. `None`
+// is returned, if any of them can not be judged yet.
+async fn reclassify_user_comic_collection<S>(
+  collection: &UserComicCollection<S>,
+  filter: &ImageFilter,
+) -> Option<UserComicCollection<S>>
+where
+  S: ComicSource + Clone,
+{
+  let identifier = collection.source.identifier();
+  let store = STORE.get();
+
+  let mut candidates = store.load_rejected(&identifier).unwrap_or_else(|error| {
+    println!(
+      "Failed to load rejected strips of {}: {}",
+      identifier, error
+    );
+    vec![]
+  });
+  for strip in collection.comic_strips.iter() {
+    if !candidates.iter().any(|candidate| candidate.id == strip.id) {
+      candidates.push(SourceStrip {
+        id: strip.id,
+        created_at: strip.created_at,
+        image_urls: strip.comics.iter().map(|comic| comic.url.clone()).collect(),
+        popularity: strip.popularity,
+      });
+    }
+  }
+
+  let moderation = Moderation::load();
+  let mut comic_strips = vec![];
+  for candidate in candidates.iter() {
+    let existing = collection
+      .comic_strips
+      .iter()
+      .find(|strip| strip.id == candidate.id);
+    let known = existing.map_or(&[][..], |strip| strip.comics.as_slice());
+    let comics = filter_strip(
+      &collection.source,
+      filter,
+      &moderation,
+      candidate.id,
+      &candidate.image_urls,
+      known,
+    )
+    .await?;
+
+    remember_rejections(&identifier, candidate, comics.len());
+    if !comics.is_empty() {
+      comic_strips.push(Arc::new(ComicStrip {
+        id: candidate.id,
+        created_at: candidate.created_at,
+        comics,
+        author: existing.and_then(|strip| strip.author.clone()),
+        title: existing.and_then(|strip| strip.title.clone()),
+        popularity: candidate.popularity,
+      }));
+    }
+  }
+
+  let mut reclassified = UserComicCollection {
+    source: collection.source.clone(),
+    max_id: collection.max_id,
+    max_amount: collection.max_amount,
+    comic_strips,
+    pending: collection.pending.clone(),
+    popularity_refreshed_at: collection.popularity_refreshed_at,
+  };
+  apply_collection_constraints(&mut reclassified);
+  reclassified.persist_changes(&collection.comic_strips);
+  // Remaining strips may have gained or lost images
+  for strip in reclassified.comic_strips.iter() {
+    log_store_error(store.save_strip(&identifier, strip));
+  }
+
+  // Rejected strips older than a full collection would never be shown
+  if reclassified.comic_strips.len() >= reclassified.max_amount {
+    if let Some(oldest) = reclassified.comic_strips.last() {
+      for candidate in candidates.iter() {
+        if candidate.created_at < oldest.created_at {
+          log_store_error(store.remove_rejected(&identifier, candidate.id));
+        }
+      }
+    }
+  }
+
+  println!("Reclassified {} strips of {}", candidates.len(), identifier);
+  Some(reclassified)
+}
+
+/// Add a rejected strip to its collection right away, once it has been
+/// included manually. Returns whether the collection of the source rejected
+/// the strip, or `None`, if its images can not be loaded yet.
+pub async fn include_rejected_strip<S>(
+  collections: &[Mutex<UserComicCollection<S>>],
+  filter: &ImageFilter,
+  source: &str,
+  id: u64,
+) -> Option<bool>
+where
+  S: ComicSource,
+{
+  let store = STORE.get();
+  let moderation = Moderation::load();
+  for collection_mut in collections.iter() {
+    let mut collection = collection_mut.lock().await;
+    let identifier = collection.source.identifier();
+    if identifier != source {
+      continue;
+    }
+    let rejected = store.load_rejected(&identifier).unwrap_or_else(|error| {
+      println!(
+        "Failed to load rejected strips of {}: {}",
+        identifier, error
+      );
+      vec![]
+    });
+    let strip = match rejected.into_iter().find(|strip| strip.id == id) {
+      Some(strip) => strip,
+      None => continue,
+    };
+
+    let existing = collection
+      .comic_strips
+      .iter()
+      .find(|known| known.id == id)
+      .cloned();
+    let known = existing
+      .as_ref()
+      .map_or(&[][..], |known| known.comics.as_slice());
+    let comics = filter_strip(
+      &collection.source,
+      filter,
+      &moderation,
+      id,
+      &strip.image_urls,
+      known,
+    )
+    .await?;
+
+    remember_rejections(&identifier, &strip, comics.len());
+    if !comics.is_empty() {
+      println!("Including strip {} in {}", id, identifier);
+      let previous_strips = collection.comic_strips.clone();
+      collection.comic_strips.retain(|known| known.id != id);
+      collection.comic_strips.push(Arc::new(ComicStrip {
+        id,
+        created_at: strip.created_at,
+        comics,
+        author: existing.as_ref().and_then(|known| known.author.clone()),
+        title: existing.as_ref().and_then(|known| known.title.clone()),
+        popularity: strip.popularity,
+      }));
+      apply_collection_constraints(&mut collection);
+      collection.persist_changes(&previous_strips);
+      // The strip may have gained images
+      if let Some(included) = collection.comic_strips.iter().find(|known| known.id == id) {
+        log_store_error(store.save_strip(&identifier, included));
+      }
+    }
+    return Some(true);
+  }
+
+  Some(false)
+}
+
+fn push_strip(comic_strips: &mut Vec<Arc<ComicStrip>>, strip: &SourceStrip, comics: Vec<Comic>) {
+  if !comics.is_empty() {
+    comic_strips.push(Arc::new(ComicStrip {
+      id: strip.id,
+      created_at: strip.created_at,
+      comics,
+      author: None,
+      title: None,
+      popularity: strip.popularity,
+    }));
+  }
+}
+
+// Reactions keep coming in after a strip has been added
+fn update_popularity(comic_strips: &mut [Arc<ComicStrip>], id: u64, popularity: Popularity) {
+  if let Some(known) = comic_strips.iter_mut().find(|known| known.id == id) {
+    if known.popularity != Some(popularity) {
+      *known = Arc::new(ComicStrip {
+        popularity: Some(popularity),
+        ..(**known).clone()
+      });
+    }
+  }
+}
+
+// Sources only report new strips, so the reactions to the known ones are
+// looked up separately. Returns whether the lookup succeeded.
+async fn refresh_popularity<S: ComicSource>(
+  source: &S,
+  comic_strips: &mut [Arc<ComicStrip>],
+) -> bool {
+  let ids: Vec<u64> = comic_strips.iter().map(|strip| strip.id).collect();
+  match source.fetch_popularity(&ids).await {
+    Ok(popularities) => {
+      for (id, popularity) in popularities {
+        update_popularity(comic_strips, id, popularity);
+      }
+      true
+    }
+    Err(error) => {
+      println!(
+        "Failed to refresh popularity of {}: {}",
+        source.identifier(),
+        error
+      );
+      false
+    }
+  }
+}
+
+async fn load_comic_image<S: ComicSource>(
+  source: &S,
+  url: &str,
+) -> Result<Arc<ComicImage>, SourceError> {
+  let data = source.load_image(url).await?;
+  Ok(Arc::new(ComicImage::cached(&data)?))
+}
+
+fn log_store_error(result: sled::Result<()>) {
+  if let Err(error) = result {
+    println!("Failed to update comic store: {}", error);
+  }
+}
+
+fn apply_collection_constraints<S>(collection: &mut UserComicCollection<S>) {
+  collection
+    .comic_strips
+    .sort_by_key(|comic| comic.created_at);
+  collection.comic_strips = collection
+    .comic_strips
+    .drain(..)
+    .rev()
+    .take(collection.max_amount)
+    .collect();
+}
+
+impl<S> UserComicCollection<S> {
+  pub fn new(source: S) -> Self {
+    Self::new_with_max_amount(source, 100)
+  }
+
+  pub fn new_with_max_amount(source: S, max_amount: usize) -> Self {
+    UserComicCollection {
+      source,
+      max_amount,
+      max_id: None,
+      comic_strips: vec![],
+      pending: vec![],
+      popularity_refreshed_at: None,
+    }
+  }
+}
+
+impl<S: ComicSource> UserComicCollection<S> {
+  /// Load strips and cursor of this collection from the comic store.
+  pub fn restore(&mut self) {
+    let identifier = self.source.identifier();
+    match STORE.get().load_strips(&identifier) {
+      Ok(strips) => self.comic_strips = strips,
+      Err(error) => println!("Failed to restore strips of {}: {}", identifier, error),
+    }
+    match STORE.get().load_pending(&identifier) {
+      Ok(pending) => self.pending = pending,
+      Err(error) => println!(
+        "Failed to restore pending strips of {}: {}",
+        identifier, error
+      ),
+    }
+    match STORE.get().max_id(&identifier) {
+      Ok(max_id) => self.max_id = max_id,
+      Err(error) => println!("Failed to restore cursor of {}: {}", identifier, error),
+    }
+    apply_collection_constraints(self);
+    println!(
+      "Restored {} strips for: {}",
+      self.comic_strips.len(),
+      identifier
+    );
+  }
+
+  pub fn insert_strip(&mut self, strip: Arc<ComicStrip>) {
+    let previous_strips = self.comic_strips.clone();
+    self.comic_strips.push(strip);
+    apply_collection_constraints(self);
+    self.persist_changes(&previous_strips);
+  }
+
+  // Write the difference to the given previous state to the comic store
+  fn persist_changes(&self, previous_strips: &[Arc<ComicStrip>]) {
+    let identifier = self.source.identifier();
+    let store = STORE.get();
+
+    for previous in previous_strips.iter() {
+      if !self
+        .comic_strips
+        .iter()
+        .any(|strip| strip.id == previous.id)
+      {
+        log_store_error(store.remove_strip(&identifier, previous.id));
+      }
+    }
+    for strip in self.comic_strips.iter() {
+      if !previous_strips
+        .iter()
+        .any(|previous| previous.id == strip.id && previous.popularity == strip.popularity)
+      {
+        log_store_error(store.save_strip(&identifier, strip));
+      }
+    }
+    log_store_error(store.save_pending(&identifier, &self.pending));
+    if let Some(max_id) = self.max_id {
+      log_store_error(store.save_max_id(&identifier, max_id));
+    }
+  }
+}
+
+// Failed refreshes are retried with an exponential backoff. The collection
+// is not locked in between, so it can still be served.
+async fn refresh_locked_collection<S>(
+  collection_mut: &Mutex<UserComicCollection<S>>,
+  filter: &ImageFilter,
+) where
+  S: ComicSource + Clone,
+{
+  let mut retry_delay = REFRESH_RETRY_DELAY;
+  for attempt in 1..=REFRESH_ATTEMPTS {
+    {
+      let mut collection = collection_mut.lock().await;
+      println!("Loading images for: {}", collection.source.identifier());
+      match refresh_user_comic_collection(&collection, filter).await {
+        Ok(refreshed) => {
+          *collection = refreshed;
+          return;
+        }
+        Err(error) => println!(
+          "Failed to refresh {} (attempt {}/{}): {}",
+          collection.source.identifier(),
+          attempt,
+          REFRESH_ATTEMPTS,
+          error
+        ),
+      }
+    }
+
+    if attempt < REFRESH_ATTEMPTS {
+      sleep(Duration::from_secs(retry_delay)).await;
+      retry_delay *= 2;
+    }
+  }
+}
+
+async fn comic_watch_task<S>(
+  collections: Arc<Vec<Mutex<UserComicCollection<S>>>>,
+  index: usize,
+  notify: Arc<Notify>,
+  filter: Arc<ImageFilter>,
+) where
+  S: ComicSource + Clone,
+{
+  loop {
+    notify.notified().await;
+    // Changes usually arrive in bursts (e.g. while copying a bunch of files).
+    // Give them some time to settle before refreshing.
+    sleep(Duration::from_secs(WATCH_SETTLE_TIME)).await;
+    refresh_locked_collection(&collections[index], &filter).await;
+  }
+}
+
+async fn collection_refresh_task<S>(
+  collections: Arc<Vec<Mutex<UserComicCollection<S>>>>,
+  index: usize,
+  filter: Arc<ImageFilter>,
+) where
+  S: ComicSource + Clone,
+{
+  loop {
+    refresh_locked_collection(&collections[index], &filter).await;
+    sleep(Duration::from_secs(CONFIG.get().twitter_refresh_interval)).await;
+  }
+}
+
+/// Filter all collections again, if the filter changed since the stored strips
+/// have been filtered, e.g. as the classifier model has been replaced. Until
+/// every collection could be judged completely, it is retried after the
+/// refresh interval.
+async fn reclassification_task<S>(
+  collections: Arc<Vec<Mutex<UserComicCollection<S>>>>,
+  filter: Arc<ImageFilter>,
+) where
+  S: ComicSource + Clone,
+{
+  // Without the model of the classifier, its identity can not be compared.
+  while !filter.detect_model().await {
+    sleep(Duration::from_secs(CONFIG.get().twitter_refresh_interval)).await;
+  }
+
+  let identity = filter.identity();
+  let store = STORE.get();
+  match store.filter_identity() {
+    Ok(Some(ref previous)) if *previous == identity => return,
+    Ok(Some(_)) => {}
+    // Strips stored before the filter identity was tracked are trusted
+    Ok(None) => {
+      log_store_error(store.save_filter_identity(&identity));
+      return;
+    }
+    Err(error) => {
+      println!("Failed to read filter identity: {}", error);
+      return;
+    }
+  }
+
+  println!("Filter changed, reclassifying stored strips");
+  // Classifications of the previous filter must not be reused
+  log_store_error(store.clear_classifications());
+
+  let mut is_done = vec![false; collections.len()];
+  loop {
+    for (index, collection_mut) in collections.iter().enumerate() {
+      let mut collection = collection_mut.lock().await;
+      if is_done[index] || !collection.source.is_filtered() {
+        is_done[index] = true;
+        continue;
+      }
+      if let Some(reclassified) = reclassify_user_comic_collection(&collection, &filter).await {
+        *collection = reclassified;
+        is_done[index] = true;
+      }
+    }
+
+    if is_done.iter().all(|done| *done) {
+      log_store_error(store.save_filter_identity(&identity));
+      println!("Reclassification finished");
+      return;
+    }
+    sleep(Duration::from_secs(CONFIG.get().twitter_refresh_interval)).await;
+  }
+}
+
+pub async fn comic_refresh_task<S>(
+  collections: Arc<Vec<Mutex<UserComicCollection<S>>>>,
+  filter: Arc<ImageFilter>,
+) where
+  S: ComicSource + Clone + 'static,
+{
+  for (index, collection_mut) in (*collections).iter().enumerate() {
+    if let Some(notify) = collection_mut.lock().await.source.change_notifier() {
+      tokio::spawn(comic_watch_task(
+        collections.clone(),
+        index,
+        notify,
+        filter.clone(),
+      ));
+    }
+  }
+
+  tokio::spawn(reclassification_task(collections.clone(), filter.clone()));
+
+  // Every collection is refreshed on its own, so retries of a failing source
+  // do not delay the others.
+  let mut tasks = vec![];
+  for index in 0..collections.len() {
+    tasks.push(tokio::spawn(collection_refresh_task(
+      collections.clone(),
+      index,
+      filter.clone(),
+    )));
+  }
+  for task in tasks {
+    if let Err(error) = task.await {
+      println!("Refresh task failed: {}", error);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use async_trait::async_trait;
+  use chrono::TimeZone;
+
+  use super::*;
+  use crate::heuristics::AspectRatioFilter;
+  use crate::source::SourceBatch;
+  use crate::testing;
+
+  // Serves wide and tall images, while the images of "offline" can not be
+  // loaded for now
+  #[derive(Clone)]
+  struct FakeSource {
+    name: &'static str,
+    strips: Vec<SourceStrip>,
+  }
+
+  #[async_trait]
+  impl ComicSource for FakeSource {
+    fn identifier(&self) -> String {
+      format!("fake:{}", self.name)
+    }
+
+    async fn fetch_strips(&self, max_id: Option<u64>) -> Result<SourceBatch, SourceError> {
+      let strips: Vec<SourceStrip> = self
+        .strips
+        .iter()
+        .filter(|strip| max_id.is_none_or(|max_id| strip.id > max_id))
+        .cloned()
+        .collect();
+      Ok(SourceBatch {
+        max_id: strips.iter().map(|strip| strip.id).max().or(max_id),
+        strips,
+        exhaustive: false,
+      })
+    }
+
+    async fn load_image(&self, url: &str) -> Result<Vec<u8>, SourceError> {
+      match url {
+        "wide" => Ok(testing::png(40, 10)),
+        "tall" => Ok(testing::png(10, 40)),
+        _ => Err(SourceError::Io(std::io::ErrorKind::NotConnected.into())),
+      }
+    }
+  }
+
+  fn source_strip(id: u64, image_urls: &[&str]) -> SourceStrip {
+    SourceStrip {
+      id,
+      created_at: Utc.timestamp_opt(id as i64 * 1000, 0).unwrap(),
+      image_urls: image_urls.iter().map(|url| url.to_string()).collect(),
+      popularity: None,
+    }
+  }
+
+  fn wide_filter() -> ImageFilter {
+    ImageFilter::from(AspectRatioFilter {
+      min: Some(1.0),
+      max: None,
+    })
+  }
+
+  fn strip_ids(collection: &UserComicCollection<FakeSource>) -> Vec<u64> {
+    collection
+      .comic_strips
+      .iter()
+      .map(|strip| strip.id)
+      .collect()
+  }
+
+  #[tokio::test]
+  async fn refresh_adds_accepted_images_newest_first() {
+    testing::init();
+    let collection = UserComicCollection::new(FakeSource {
+      name: "accepted",
+      strips: vec![
+        source_strip(1, &["wide"]),
+        source_strip(2, &["tall", "wide"]),
+        source_strip(3, &["tall"]),
+      ],
+    });
+
+    let refreshed = refresh_user_comic_collection(&collection, &wide_filter())
+      .await
+      .unwrap();
+
+    assert_eq!(strip_ids(&refreshed), vec![2, 1]);
+    let urls: Vec<&str> = refreshed.comic_strips[0]
+      .comics
+      .iter()
+      .map(|comic| comic.url.as_str())
+      .collect();
+    assert_eq!(urls, vec!["wide"]);
+    assert_eq!(refreshed.max_id, Some(3));
+    assert!(refreshed.pending.is_empty());
+
+    // Strips with rejected images are kept to filter them again
+    let rejected: Vec<u64> = STORE
+      .get()
+      .load_rejected("fake:accepted")
+      .unwrap()
+      .iter()
+      .map(|strip| strip.id)
+      .collect();
+    assert_eq!(rejected, vec![2, 3]);
+  }
+
+  #[tokio::test]
+  async fn refresh_continues_after_the_known_strips() {
+    testing::init();
+    let mut collection = UserComicCollection::new(FakeSource {
+      name: "continued",
+      strips: vec![source_strip(1, &["wide"])],
+    });
+    collection = refresh_user_comic_collection(&collection, &wide_filter())
+      .await
+      .unwrap();
+
+    collection.source.strips.push(source_strip(2, &["wide"]));
+    collection = refresh_user_comic_collection(&collection, &wide_filter())
+      .await
+      .unwrap();
+    assert_eq!(strip_ids(&collection), vec![2, 1]);
+
+    let mut restored = UserComicCollection::new(collection.source.clone());
+    restored.restore();
+    assert_eq!(strip_ids(&restored), vec![2, 1]);
+    assert_eq!(restored.max_id, Some(2));
+  }
+
+  #[tokio::test]
+  async fn refresh_keeps_strips_with_unavailable_images_pending() {
+    testing::init();
+    let collection = UserComicCollection::new(FakeSource {
+      name: "pending",
+      strips: vec![source_strip(1, &["wide", "offline"])],
+    });
+
+    let refreshed = refresh_user_comic_collection(&collection, &wide_filter())
+      .await
+      .unwrap();
+    assert!(refreshed.comic_strips.is_empty());
+    assert_eq!(refreshed.pending.len(), 1);
+    assert_eq!(refreshed.pending[0].attempts, 1);
+
+    let refreshed = refresh_user_comic_collection(&refreshed, &wide_filter())
+      .await
+      .unwrap();
+    assert_eq!(refreshed.pending[0].attempts, 2);
+  }
+
+  #[tokio::test]
+  async fn changed_filter_reclassifies_known_and_rejected_strips() {
+    testing::init();
+    let collection = UserComicCollection::new(FakeSource {
+      name: "reclassified",
+      strips: vec![
+        source_strip(101, &["wide"]),
+        source_strip(102, &["tall"]),
+        source_strip(103, &["tall", "wide"]),
+      ],
+    });
+    let collection = refresh_user_comic_collection(&collection, &wide_filter())
+      .await
+      .unwrap();
+    assert_eq!(strip_ids(&collection), vec![103, 101]);
+
+    let tall_filter = ImageFilter::from(AspectRatioFilter {
+      min: None,
+      max: Some(1.0),
+    });
+    let identity = tall_filter.identity();
+    STORE
+      .get()
+      .save_filter_identity(&wide_filter().identity())
+      .unwrap();
+    let collections = Arc::new(vec![Mutex::new(collection)]);
+    reclassification_task(collections.clone(), Arc::new(tall_filter)).await;
+
+    // Rejected strips are brought back, while the accepted one is dropped
+    let collection = collections[0].lock().await;
+    assert_eq!(strip_ids(&collection), vec![103, 102]);
+    let urls: Vec<&str> = collection.comic_strips[0]
+      .comics
+      .iter()
+      .map(|comic| comic.url.as_str())
+      .collect();
+    assert_eq!(urls, vec!["tall"]);
+    assert_eq!(STORE.get().filter_identity().unwrap(), Some(identity));
+
+    let mut restored = UserComicCollection::new(collection.source.clone());
+    restored.restore();
+    assert_eq!(strip_ids(&restored), vec![103, 102]);
+  }
+}
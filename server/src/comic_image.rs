@@ -1,21 +1,25 @@
 use std::io::Cursor;
 
+use image::error::{LimitError, LimitErrorKind};
 use image::png::PngEncoder;
-use image::{ColorType, DynamicImage, GenericImageView, ImageFormat};
+use image::{DynamicImage, GenericImageView, ImageError, ImageFormat, ImageResult};
 
-use crate::dithering;
+use crate::blob_cache::{content_hash, BlobHandle};
+use crate::{BLOBS, CONFIG};
 
-#[inline(always)]
-fn is_odd(value: u32) -> bool {
-  value & 0x1 == 0x1
+#[derive(Debug)]
+enum ImageData {
+  /// Compressed PNG kept in memory
+  Png(Vec<u8>),
+  /// Downloaded data within the blob cache, which is only read on demand
+  Blob(BlobHandle),
 }
 
 #[derive(Debug)]
 pub struct ComicImage {
-  data: Vec<u8>,
+  data: ImageData,
   width: u32,
   height: u32,
-  color: ColorType,
 }
 
 impl From<DynamicImage> for ComicImage {
@@ -37,74 +41,97 @@ impl From<DynamicImage> for ComicImage {
       .unwrap();
 
     Self {
-      data,
+      data: ImageData::Png(data),
       width,
       height,
-      color,
     }
   }
 }
 
 impl ComicImage {
-  pub fn dynamic_image(&self) -> DynamicImage {
-    let mut img = image::io::Reader::new(Cursor::new(&self.data));
-    img.set_format(ImageFormat::Png);
-    img.decode().unwrap()
+  /// Comic image backed by the blob cache. The image data is loaded lazily
+  /// whenever it is needed.
+  pub fn from_blob(handle: BlobHandle, width: u32, height: u32) -> Self {
+    Self {
+      data: ImageData::Blob(handle),
+      width,
+      height,
+    }
   }
 
-  pub fn png_image(&self) -> Vec<u8> {
-    self.data.clone()
-  }
+  /// Store downloaded image data within the blob cache. The data is decoded
+  /// once, to ensure it is a supported image. Images exceeding the configured
+  /// dimensions are rejected before decoding them.
+  pub fn cached(data: &[u8]) -> ImageResult<Self> {
+    let max_dimension = CONFIG.get().max_image_dimension;
+    let (width, height) = image::io::Reader::new(Cursor::new(data))
+      .with_guessed_format()?
+      .into_dimensions()?;
+    if width > max_dimension || height > max_dimension {
+      return Err(ImageError::Limits(LimitError::from_kind(
+        LimitErrorKind::DimensionError,
+      )));
+    }
+    image::io::Reader::new(Cursor::new(data))
+      .with_guessed_format()?
+      .decode()?;
 
-  pub fn dithered_png_image(&self) -> Vec<u8> {
-    let mut out_bytes: Vec<u8> = Vec::new();
+    let handle = BLOBS.get().insert(data)?;
 
-    let dithered = dithering::quantize_to_3bit(&self.dynamic_image(), dithering::floyd_steinberg());
+    Ok(Self::from_blob(handle, width, height))
+  }
 
-    DynamicImage::ImageLuma8(dithered)
-      .write_to(&mut out_bytes, image::ImageOutputFormat::Png)
-      .unwrap();
+  /// Content hash identifying the image. Downloaded images are identified by
+  /// their blob, every other one by its PNG data.
+  pub fn content_hash(&self) -> String {
+    match self.data {
+      ImageData::Png(ref data) => content_hash(data),
+      ImageData::Blob(ref handle) => handle.hash().to_string(),
+    }
+  }
 
-    out_bytes
+  /// Size of the encoded image data in bytes
+  pub fn data_size(&self) -> u64 {
+    match self.data {
+      ImageData::Png(ref data) => data.len() as u64,
+      ImageData::Blob(ref handle) => handle.size(),
+    }
+  }
+
+  /// Hash of the backing blob, if the image is stored in the blob cache
+  pub fn blob_hash(&self) -> Option<&str> {
+    match self.data {
+      ImageData::Png(_) => None,
+      ImageData::Blob(ref handle) => Some(handle.hash()),
+    }
   }
 
-  pub fn inkplate_image(&self) -> Vec<u8> {
-    let dithered =
-      dithering::quantize_to_3bit(&self.dynamic_image(), dithering::jarvis_judice_ninke());
-    let (width, height) = dithered.dimensions();
-
-    // Minimize possible reallocations
-    let mut out_bytes: Vec<u8> = Vec::with_capacity((width * height / 2) as usize);
-
-    // We are encoding 2 3bit pixels per bit one in the upper byte, one in the
-    // lower. Furthermore we are always starting a new byte at the beginning of
-    // each row. Therefore the last one might need padding if the width is odd
-    let odd_width = is_odd(width);
-
-    for y in 0..height {
-      let mut current_byte: u8 = 0x0;
-      for x in 0..width {
-        let p = dithering::get_pixel(&dithered, x, y);
-
-        if is_odd(x) {
-          // First of two pixels (high nible)
-          current_byte = p & 0xf0
-        } else {
-          // Second of two pixels (low nible)
-          current_byte = current_byte | (p >> 4);
-
-          // Write finished byte
-          out_bytes.push(current_byte);
-        }
-
-        if odd_width && x == width - 1 {
-          // Write out last byte with padding before switching lines.
-          out_bytes.push(current_byte);
-        }
+  /// Decode the image. This may fail for blob backed images, whose data is
+  /// read from disk.
+  pub fn dynamic_image(&self) -> ImageResult<DynamicImage> {
+    match self.data {
+      ImageData::Png(ref data) => {
+        let mut img = image::io::Reader::new(Cursor::new(data));
+        img.set_format(ImageFormat::Png);
+        img.decode()
       }
+      ImageData::Blob(ref handle) => image::io::Reader::new(Cursor::new(handle.read()?))
+        .with_guessed_format()?
+        .decode(),
     }
+  }
 
-    out_bytes
+  pub fn png_image(&self) -> ImageResult<Vec<u8>> {
+    match self.data {
+      ImageData::Png(ref data) => Ok(data.clone()),
+      ImageData::Blob(_) => {
+        let mut out_bytes: Vec<u8> = Vec::new();
+        self
+          .dynamic_image()?
+          .write_to(&mut out_bytes, image::ImageOutputFormat::Png)?;
+        Ok(out_bytes)
+      }
+    }
   }
 
   pub fn width(&self) -> u32 {
@@ -118,8 +145,4 @@ impl ComicImage {
   pub fn dimensions(&self) -> (u32, u32) {
     (self.width, self.height)
   }
-
-  pub fn color(&self) -> ColorType {
-    self.color
-  }
 }
@@ -1,12 +1,16 @@
 use std::sync::Arc;
 
+use chrono::{DateTime, Utc};
 use image::imageops::FilterType;
 use image::GenericImage;
 use image::{imageops, DynamicImage, GenericImageView, ImageBuffer, Rgba};
 
+use crate::collection::ComicStrip;
 use crate::comic_image::ComicImage;
-use crate::layout::{CalculateLayout, ColumnLayout, Layout, RowLayout, SingleLayout};
-use crate::random_comic_strips;
+use crate::frame::FrameStrip;
+use crate::layout::{CalculateLayout, ColumnLayout, Layout, RowLayout, SingleLayout, StripLayout};
+use crate::selection::Candidate;
+use crate::SELECTOR;
 
 const COMPOSITION_WIDTH: f64 = 1200.0;
 const COMPOSITION_HEIGHT: f64 = 825.0;
@@ -39,66 +43,144 @@ struct DrawingInstruction {
   area: Rectangle,
 }
 
-pub async fn create_composition_image() -> DynamicImage {
-  let comic_strips = random_comic_strips().await;
-  assert!(comic_strips.len() > 0);
+/// Composed image along with the strips shown on it, in the order they have
+/// been laid out.
+pub struct Composition {
+  pub image: DynamicImage,
+  pub strips: Vec<FrameStrip>,
+}
+
+impl Composition {
+  fn new(image: DynamicImage, shown: &[&Candidate]) -> Self {
+    let strips = shown
+      .iter()
+      .map(|candidate| FrameStrip {
+        source: candidate.source.clone(),
+        strip_id: candidate.strip.id,
+      })
+      .collect();
+    Composition { image, strips }
+  }
+}
+
+/// Composition of the strips selected for the device by the given seed at
+/// `now`, or `None`, if there is no strip to show. Its strips count as shown
+/// to the device.
+pub fn create_composition(device: &str, seed: u64, now: DateTime<Utc>) -> Option<Composition> {
+  let candidates = SELECTOR.get().comic_strips(device, seed, now);
+  let (image, shown) = compose(&candidates)?;
+  SELECTOR.get().mark_shown(device, &shown, now);
+  Some(Composition::new(image, &shown))
+}
 
-  let primary_strip = &comic_strips[0];
-  let primary_image = primary_strip.comics[0].image();
+/// Composition of the strips selected by the seed alone, regardless of the
+/// history of any device, or `None`, if there is no strip to show. Its strips
+/// do not count as shown.
+pub fn seeded_composition(seed: u64, now: DateTime<Utc>) -> Option<Composition> {
+  let candidates = SELECTOR.get().seeded_strips(seed, now);
+  let (image, shown) = compose(&candidates)?;
+  Some(Composition::new(image, &shown))
+}
+
+// Lay out the first candidate along with the following ones fitting next to
+// it. Only the candidates actually shown are returned with the image, so
+// composing them alone results in the same image.
+fn compose(candidates: &[Candidate]) -> Option<(DynamicImage, Vec<&Candidate>)> {
+  if candidates.is_empty() {
+    return None;
+  }
+
+  let primary_strip = &candidates[0].strip;
+  let mut shown = vec![&candidates[0]];
+  let is_multi_image_strip = primary_strip.comics.len() > 1;
+  let primary_image = if is_multi_image_strip {
+    strip_image(primary_strip)
+  } else {
+    primary_strip.comics[0].image()
+  };
   let primary_size = size_to_fit(
-    &*primary_image,
+    &primary_image,
     Size::new(COMPOSITION_WIDTH, COMPOSITION_HEIGHT),
   );
 
   // Decide between RowLayout, ColumnLayout or SingleLayout
-  let layout;
+  let mut layout = None;
   if primary_size.h < COMPOSITION_HEIGHT
     && COMPOSITION_HEIGHT - primary_size.h > COMPOSITION_HEIGHT * COMPOSITION_SPLIT_MIN
-    && comic_strips.len() > 1
+    && candidates.len() > 1
   {
     let mut filled_width = 0.0;
     let mut secondary_images: Vec<Arc<ComicImage>> = vec![];
-    for secondary_strip in &comic_strips[1..] {
+    for candidate in &candidates[1..] {
+      let secondary_strip = &candidate.strip;
+      if secondary_strip.comics.len() > 1 {
+        // Multi image strips would only be shown partially as secondary
+        continue;
+      }
       let secondary_image = secondary_strip.comics[0].image();
       let secondary_size = size_to_fit(
-        &*secondary_image,
+        &secondary_image,
         Size::new(COMPOSITION_WIDTH, COMPOSITION_HEIGHT - primary_size.h),
       );
       if filled_width + secondary_size.w <= COMPOSITION_WIDTH {
         secondary_images.push(secondary_image.clone());
+        shown.push(candidate);
         filled_width += secondary_size.w;
       }
     }
-    layout = Layout::from(RowLayout::new_with_margin(
-      primary_image,
-      secondary_images,
-      COMPOSITION_MARGIN,
-    ));
+    if !secondary_images.is_empty() {
+      layout = Some(Layout::from(RowLayout::new_with_margin(
+        primary_image.clone(),
+        secondary_images,
+        COMPOSITION_MARGIN,
+      )));
+    }
   } else if primary_size.w < COMPOSITION_WIDTH
     && COMPOSITION_WIDTH - primary_size.w > COMPOSITION_WIDTH * COMPOSITION_SPLIT_MIN
-    && comic_strips.len() > 1
+    && candidates.len() > 1
   {
     let mut filled_height = 0.0;
     let mut secondary_images: Vec<Arc<ComicImage>> = vec![];
-    for secondary_strip in &comic_strips[1..] {
+    for candidate in &candidates[1..] {
+      let secondary_strip = &candidate.strip;
+      if secondary_strip.comics.len() > 1 {
+        // Multi image strips would only be shown partially as secondary
+        continue;
+      }
       let secondary_image = secondary_strip.comics[0].image();
       let secondary_size = size_to_fit(
-        &*secondary_image,
+        &secondary_image,
         Size::new(COMPOSITION_WIDTH - primary_size.w, COMPOSITION_HEIGHT),
       );
       if filled_height + secondary_size.h <= COMPOSITION_HEIGHT {
         secondary_images.push(secondary_image.clone());
+        shown.push(candidate);
         filled_height += secondary_size.h;
       }
     }
-    layout = Layout::from(ColumnLayout::new_with_margin(
-      primary_image,
-      secondary_images,
-      COMPOSITION_MARGIN,
-    ));
-  } else {
-    layout = Layout::from(SingleLayout::new_with_margin(primary_image.clone(), COMPOSITION_MARGIN));
+    if !secondary_images.is_empty() {
+      layout = Some(Layout::from(ColumnLayout::new_with_margin(
+        primary_image.clone(),
+        secondary_images,
+        COMPOSITION_MARGIN,
+      )));
+    }
   }
+  // Without any secondary strip fitting next to it, the primary one is shown
+  // on its own.
+  let layout = layout.unwrap_or_else(|| {
+    if is_multi_image_strip {
+      Layout::from(StripLayout::new_with_margin(
+        strip_images(primary_strip),
+        COMPOSITION_MARGIN,
+      ))
+    } else {
+      Layout::from(SingleLayout::new_with_margin(
+        primary_image,
+        COMPOSITION_MARGIN,
+      ))
+    }
+  });
 
   let mut target = ImageBuffer::from_pixel(
     COMPOSITION_WIDTH as u32,
@@ -118,11 +200,61 @@ pub async fn create_composition_image() -> DynamicImage {
   });
 
   for instr in instructions {
-    resize_and_overlay(&mut target, &instr.image.dynamic_image(), instr.area);
+    match instr.image.dynamic_image() {
+      Ok(image) => resize_and_overlay(&mut target, &image, instr.area),
+      Err(error) => println!("Skipping unreadable comic image: {}", error),
+    }
   }
 
-  DynamicImage::ImageRgba8(target)
- }
+  Some((DynamicImage::ImageRgba8(target), shown))
+}
+
+fn strip_images(strip: &ComicStrip) -> Vec<Arc<ComicImage>> {
+  strip.comics.iter().map(|comic| comic.image()).collect()
+}
+
+// Combine all images of a strip into one image, which can be placed within a
+// layout like any other comic.
+fn strip_image(strip: &ComicStrip) -> Arc<ComicImage> {
+  let layout = StripLayout::new_with_margin(strip_images(strip), COMPOSITION_MARGIN);
+  let instructions = layout.calculate();
+
+  let min_x = instructions.iter().map(|instr| instr.x).min().unwrap_or(0);
+  let min_y = instructions.iter().map(|instr| instr.y).min().unwrap_or(0);
+  let max_x = instructions
+    .iter()
+    .map(|instr| instr.x + instr.w)
+    .max()
+    .unwrap_or(1);
+  let max_y = instructions
+    .iter()
+    .map(|instr| instr.y + instr.h)
+    .max()
+    .unwrap_or(1);
+
+  let mut target = ImageBuffer::from_pixel(max_x - min_x, max_y - min_y, COMPOSITION_BACKGROUND);
+  for instr in instructions {
+    let image = match instr.image.dynamic_image() {
+      Ok(image) => image,
+      Err(error) => {
+        println!("Skipping unreadable strip image: {}", error);
+        continue;
+      }
+    };
+    resize_and_overlay(
+      &mut target,
+      &image,
+      Rectangle {
+        x: instr.x - min_x,
+        y: instr.y - min_y,
+        w: instr.w,
+        h: instr.h,
+      },
+    );
+  }
+
+  Arc::new(ComicImage::from(DynamicImage::ImageRgba8(target)))
+}
 
 fn size_to_fit(image: &ComicImage, max_size: Size) -> Size {
   let width = image.width();
@@ -158,3 +290,55 @@ where
 
   imageops::overlay(bottom, &resized_top, area.x, area.y);
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::collection::Comic;
+  use crate::selection::{Selection, SelectionConfig};
+  use image::RgbImage;
+
+  fn candidate(source: &str, id: u64, width: u32, height: u32) -> Candidate {
+    let shade = (id * 40) as u8;
+    let image = RgbImage::from_pixel(width, height, image::Rgb([shade, shade, shade]));
+    Candidate {
+      source: source.to_string(),
+      strip: Arc::new(ComicStrip {
+        id,
+        comics: vec![Comic::new(
+          format!("https://comic.example/{}.png", id),
+          Arc::new(ComicImage::from(DynamicImage::ImageRgb8(image))),
+        )],
+        created_at: Utc::now(),
+        author: None,
+        title: None,
+        popularity: None,
+      }),
+    }
+  }
+
+  fn seeded(selection: &Selection, candidates: &[Candidate], seed: u64) -> Composition {
+    let ordered = selection.seeded_order(candidates.to_vec(), seed, Utc::now());
+    let (image, shown) = compose(&ordered).unwrap();
+    Composition::new(image, &shown)
+  }
+
+  #[test]
+  fn same_seed_results_in_the_same_composition() {
+    let selection = Selection::from(&SelectionConfig::default());
+    let candidates = vec![
+      candidate("a", 1, 400, 100),
+      candidate("a", 2, 300, 100),
+      candidate("b", 3, 100, 300),
+      candidate("b", 4, 200, 100),
+      candidate("c", 5, 100, 100),
+    ];
+
+    for seed in 0..5 {
+      let first = seeded(&selection, &candidates, seed);
+      let second = seeded(&selection, &candidates, seed);
+      assert_eq!(first.strips, second.strips);
+      assert_eq!(first.image.as_bytes(), second.image.as_bytes());
+    }
+  }
+}
@@ -0,0 +1,293 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tokio::sync::Notify;
+
+use crate::source::{stable_id, ComicSource, SourceBatch, SourceError, SourceStrip};
+
+const IMAGE_EXTENSIONS: [&str; 5] = ["png", "jpg", "jpeg", "gif", "webp"];
+
+fn is_image(path: &Path) -> bool {
+  match path.extension().and_then(|extension| extension.to_str()) {
+    Some(extension) => IMAGE_EXTENSIONS.contains(&extension.to_lowercase().as_str()),
+    None => false,
+  }
+}
+
+fn modified(path: &Path) -> Result<SystemTime, SourceError> {
+  Ok(fs::metadata(path)?.modified()?)
+}
+
+// Symbolic links to directories are not followed, as they might form loops.
+fn images_below(directory: &Path) -> Result<Vec<PathBuf>, SourceError> {
+  let mut images = vec![];
+  for entry in fs::read_dir(directory)? {
+    let entry = entry?;
+    let path = entry.path();
+    if entry.file_type()?.is_dir() {
+      images.extend(images_below(&path)?);
+    } else if is_image(&path) {
+      images.push(path);
+    }
+  }
+  images.sort();
+  Ok(images)
+}
+
+/// Images stored within a local directory tree.
+///
+/// Every image directly inside the directory is a strip on its own. Every
+/// subdirectory is a single strip, consisting of all images below it in
+/// alphabetical order.
+#[derive(Clone)]
+pub struct DirectorySource {
+  path: PathBuf,
+  notify: Arc<Notify>,
+  watcher: Option<Arc<Mutex<RecommendedWatcher>>>,
+}
+
+impl DirectorySource {
+  pub fn new(path: PathBuf) -> Self {
+    let notify = Arc::new(Notify::new());
+
+    let watcher_notify = notify.clone();
+    let watcher = notify::recommended_watcher(move |result: notify::Result<notify::Event>| {
+      if let Ok(event) = result {
+        match event.kind {
+          EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_) => {
+            watcher_notify.notify_one()
+          }
+          _ => {}
+        }
+      }
+    })
+    .and_then(|mut watcher| {
+      watcher.watch(&path, RecursiveMode::Recursive)?;
+      Ok(watcher)
+    });
+
+    let watcher = match watcher {
+      Ok(watcher) => Some(Arc::new(Mutex::new(watcher))),
+      Err(error) => {
+        println!("Could not watch {:?}: {}", path, error);
+        None
+      }
+    };
+
+    DirectorySource {
+      path,
+      notify,
+      watcher,
+    }
+  }
+
+  fn strip(&self, entry: &Path, images: Vec<PathBuf>) -> Result<SourceStrip, SourceError> {
+    // Changes to any of the images result in a new strip
+    let mut identity = entry
+      .strip_prefix(&self.path)
+      .unwrap_or(entry)
+      .to_string_lossy()
+      .to_string();
+    let mut created_at = SystemTime::UNIX_EPOCH;
+    for image in images.iter() {
+      let image_modified = modified(image)?;
+      let timestamp = image_modified
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+      identity.push_str(&format!(":{}@{}", image.to_string_lossy(), timestamp));
+      created_at = created_at.max(image_modified);
+    }
+
+    Ok(SourceStrip {
+      id: stable_id(&identity),
+      created_at: DateTime::<Utc>::from(created_at),
+      image_urls: images
+        .iter()
+        .map(|image| image.to_string_lossy().to_string())
+        .collect(),
+      popularity: None,
+    })
+  }
+
+  // Scanning the directory tree blocks, so it has to run on the blocking
+  // thread pool.
+  fn scan(&self) -> Result<Vec<SourceStrip>, SourceError> {
+    let mut strips = vec![];
+    for entry in fs::read_dir(&self.path)? {
+      let entry = entry?;
+      let path = entry.path();
+      let images = if entry.file_type()?.is_dir() {
+        images_below(&path)?
+      } else if is_image(&path) {
+        vec![path.clone()]
+      } else {
+        continue;
+      };
+
+      if !images.is_empty() {
+        strips.push(self.strip(&path, images)?);
+      }
+    }
+    Ok(strips)
+  }
+}
+
+#[async_trait]
+impl ComicSource for DirectorySource {
+  fn identifier(&self) -> String {
+    format!("directory:{}", self.path.to_string_lossy())
+  }
+
+  async fn fetch_strips(&self, _max_id: Option<u64>) -> Result<SourceBatch, SourceError> {
+    let source = self.clone();
+    let strips = tokio::task::spawn_blocking(move || source.scan())
+      .await
+      .map_err(std::io::Error::other)??;
+
+    println!("Found {} strips: processing...", strips.len());
+
+    Ok(SourceBatch {
+      strips,
+      max_id: None,
+      exhaustive: true,
+    })
+  }
+
+  async fn load_image(&self, url: &str) -> Result<Vec<u8>, SourceError> {
+    println!(" -> {}", url);
+    Ok(tokio::fs::read(url).await?)
+  }
+
+  fn change_notifier(&self) -> Option<Arc<Notify>> {
+    self.watcher.as_ref().map(|_| self.notify.clone())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::TimeZone;
+  use std::fs::File;
+  use std::time::Duration;
+
+  use super::*;
+
+  // Empty directory of its own for every test
+  fn directory(name: &str) -> PathBuf {
+    let directory = std::env::temp_dir().join(format!(
+      "twitter_comic_streamer_{}_directory_{}",
+      std::process::id(),
+      name
+    ));
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(&directory).unwrap();
+    directory
+  }
+
+  fn write(path: &Path, modified_secs: u64) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, path.to_string_lossy().as_bytes()).unwrap();
+    File::options()
+      .write(true)
+      .open(path)
+      .unwrap()
+      .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(modified_secs))
+      .unwrap();
+  }
+
+  async fn fetch(directory: &Path) -> Vec<SourceStrip> {
+    let batch = DirectorySource::new(directory.to_path_buf())
+      .fetch_strips(None)
+      .await
+      .unwrap();
+    assert!(batch.exhaustive);
+    let mut strips = batch.strips;
+    strips.sort_by(|a, b| a.image_urls.cmp(&b.image_urls));
+    strips
+  }
+
+  fn url(path: PathBuf) -> String {
+    path.to_string_lossy().to_string()
+  }
+
+  #[tokio::test]
+  async fn every_image_file_is_a_strip() {
+    let directory = directory("files");
+    write(&directory.join("1.png"), 1_600_000_000);
+    write(&directory.join("2.JPG"), 1_600_000_000);
+    write(&directory.join("notes.txt"), 1_600_000_000);
+
+    let strips = fetch(&directory).await;
+    assert_eq!(strips.len(), 2);
+    assert_eq!(strips[0].image_urls, vec![url(directory.join("1.png"))]);
+    assert_eq!(strips[1].image_urls, vec![url(directory.join("2.JPG"))]);
+    assert_ne!(strips[0].id, strips[1].id);
+
+    let source = DirectorySource::new(directory.clone());
+    let data = source.load_image(&strips[0].image_urls[0]).await.unwrap();
+    assert_eq!(data, url(directory.join("1.png")).into_bytes());
+  }
+
+  #[tokio::test]
+  async fn subdirectories_are_strips_of_all_images_below() {
+    let directory = directory("subdirectories");
+    write(&directory.join("strip/2.png"), 1_600_000_000);
+    write(&directory.join("strip/1.png"), 1_600_000_000);
+    write(&directory.join("strip/bonus/3.gif"), 1_600_000_000);
+    write(&directory.join("strip/notes.txt"), 1_600_000_000);
+    fs::create_dir_all(directory.join("empty")).unwrap();
+
+    let strips = fetch(&directory).await;
+    assert_eq!(strips.len(), 1);
+    assert_eq!(
+      strips[0].image_urls,
+      vec![
+        url(directory.join("strip/1.png")),
+        url(directory.join("strip/2.png")),
+        url(directory.join("strip/bonus/3.gif")),
+      ]
+    );
+  }
+
+  #[tokio::test]
+  async fn modification_times_determine_id_and_creation() {
+    let directory = directory("modified");
+    write(&directory.join("strip/1.png"), 1_600_000_000);
+    write(&directory.join("strip/2.png"), 1_600_000_100);
+
+    let strip = fetch(&directory).await.remove(0);
+    assert_eq!(
+      strip.created_at,
+      Utc.timestamp_opt(1_600_000_100, 0).unwrap()
+    );
+    assert_eq!(fetch(&directory).await[0].id, strip.id);
+
+    // Touching any image results in a new strip
+    write(&directory.join("strip/1.png"), 1_600_000_200);
+    let changed = fetch(&directory).await.remove(0);
+    assert_ne!(changed.id, strip.id);
+    assert_eq!(
+      changed.created_at,
+      Utc.timestamp_opt(1_600_000_200, 0).unwrap()
+    );
+  }
+
+  #[cfg(unix)]
+  #[tokio::test]
+  async fn symlinked_directories_are_not_followed() {
+    let directory = directory("symlinks");
+    write(&directory.join("strip/1.png"), 1_600_000_000);
+    std::os::unix::fs::symlink(directory.join("strip"), directory.join("strip/loop")).unwrap();
+
+    let strips = fetch(&directory).await;
+    assert_eq!(strips.len(), 1);
+    assert_eq!(
+      strips[0].image_urls,
+      vec![url(directory.join("strip/1.png"))]
+    );
+  }
+}
@@ -11,9 +11,9 @@ impl Dithering {
   #[inline(always)]
   fn new(kernel: Kernel5x5) -> Self {
     let mut normalization: u32 = 0;
-    for row in 0..5 {
-      for col in 0..5 {
-        normalization += kernel[row][col];
+    for row in kernel.iter() {
+      for value in row.iter() {
+        normalization += value;
       }
     }
 
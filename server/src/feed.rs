@@ -0,0 +1,318 @@
+use async_trait::async_trait;
+use feed_rs::model::Entry;
+use regex::Regex;
+use reqwest::Url;
+use std::sync::{Arc, Mutex};
+
+use crate::http;
+use crate::source::{stable_id, ComicSource, SourceBatch, SourceError, SourceStrip};
+
+const IMAGE_EXTENSIONS: [&str; 5] = [".png", ".jpg", ".jpeg", ".gif", ".webp"];
+
+fn looks_like_image(url: &str) -> bool {
+  let path = url.split(['?', '#']).next().unwrap_or("").to_lowercase();
+  IMAGE_EXTENSIONS
+    .iter()
+    .any(|extension| path.ends_with(extension))
+}
+
+/// RSS 2.0 or Atom feed of a webcomic.
+///
+/// Images are taken from enclosures, `media:content` elements and `<img>` tags
+/// embedded into the HTML content of every entry.
+#[derive(Debug, Clone)]
+pub struct FeedSource {
+  url: String,
+  img_regex: Regex,
+  /// Ids of the entries published at the cursor timestamp, which have
+  /// already been fetched.
+  cursor_ids: Arc<Mutex<Vec<u64>>>,
+}
+
+impl FeedSource {
+  pub fn new(url: String) -> Self {
+    FeedSource {
+      url,
+      img_regex: Regex::new(r#"(?i)<img[^>]+src\s*=\s*["']([^"']+)["']"#).unwrap(),
+      cursor_ids: Arc::new(Mutex::new(vec![])),
+    }
+  }
+
+  fn image_urls(&self, entry: &Entry) -> Vec<String> {
+    let mut urls: Vec<String> = vec![];
+
+    for media in entry.media.iter() {
+      for content in media.content.iter() {
+        let url = match content.url {
+          Some(ref url) => url.to_string(),
+          None => continue,
+        };
+
+        let is_image = match content.content_type {
+          Some(ref content_type) => content_type.to_string().starts_with("image/"),
+          None => looks_like_image(&url),
+        };
+
+        if is_image {
+          urls.push(url);
+        }
+      }
+    }
+
+    let mut html = vec![];
+    if let Some(ref content) = entry.content {
+      if let Some(ref body) = content.body {
+        html.push(body.as_str());
+      }
+    }
+    if let Some(ref summary) = entry.summary {
+      html.push(summary.content.as_str());
+    }
+
+    let base = entry
+      .links
+      .first()
+      .and_then(|link| Url::parse(&link.href).ok())
+      .or_else(|| Url::parse(&self.url).ok());
+
+    for fragment in html {
+      for capture in self.img_regex.captures_iter(fragment) {
+        let src = capture[1].replace("&amp;", "&");
+        let resolved = match base {
+          Some(ref base) => base.join(&src).map(|url| url.to_string()).ok(),
+          None => Url::parse(&src).map(|url| url.to_string()).ok(),
+        };
+
+        if let Some(url) = resolved {
+          urls.push(url);
+        }
+      }
+    }
+
+    // The same image is quite often referenced as enclosure and inline.
+    let mut unique_urls = vec![];
+    for url in urls {
+      if !unique_urls.contains(&url) {
+        unique_urls.push(url);
+      }
+    }
+
+    unique_urls
+  }
+}
+
+#[async_trait]
+impl ComicSource for FeedSource {
+  fn identifier(&self) -> String {
+    format!("feed:{}", self.url)
+  }
+
+  async fn fetch_strips(&self, max_id: Option<u64>) -> Result<SourceBatch, SourceError> {
+    let response = http::client()
+      .get(self.url.as_str())
+      .send()
+      .await?
+      .error_for_status()?;
+    let body = http::read_body(response).await?;
+
+    let feed = feed_rs::parser::Builder::new()
+      .base_uri(Some(&self.url))
+      .build()
+      .parse(body.as_slice())?;
+
+    println!(
+      "Received {} feed entries: processing...",
+      feed.entries.len()
+    );
+
+    // Feeds do not have any ordered ids. Therefore the publication timestamp
+    // is used as cursor instead. Several entries may share a timestamp, so
+    // entries at the cursor are told apart by their ids. Those ids are not
+    // persisted, which results in such entries being fetched once more after
+    // a restart.
+    let mut cursor_ids = self.cursor_ids.lock().unwrap();
+    let mut new_max_id = max_id;
+    let mut strips = vec![];
+    for entry in feed.entries.iter() {
+      let created_at = match entry.published.or(entry.updated) {
+        Some(created_at) => created_at,
+        // Entries without any date can not be tracked by the cursor.
+        None => continue,
+      };
+      let timestamp = created_at.timestamp_millis() as u64;
+      let id = stable_id(&entry.id);
+
+      if let Some(max_id) = max_id {
+        if timestamp < max_id || (timestamp == max_id && cursor_ids.contains(&id)) {
+          continue;
+        }
+      }
+
+      new_max_id = Some(new_max_id.map_or(timestamp, |max_id| max_id.max(timestamp)));
+
+      strips.push(SourceStrip {
+        id,
+        created_at,
+        image_urls: self.image_urls(entry),
+        popularity: None,
+      });
+    }
+
+    if new_max_id != max_id {
+      cursor_ids.clear();
+    }
+    cursor_ids.extend(
+      strips
+        .iter()
+        .filter(|strip| Some(strip.created_at.timestamp_millis() as u64) == new_max_id)
+        .map(|strip| strip.id),
+    );
+    drop(cursor_ids);
+
+    Ok(SourceBatch {
+      strips,
+      max_id: new_max_id,
+      exhaustive: false,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing;
+
+  const RSS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0" xmlns:content="http://purl.org/rss/1.0/modules/content/" xmlns:media="http://search.yahoo.com/mrss/">
+  <channel>
+    <title>Comic</title>
+    <link>https://comic.example</link>
+    <item>
+      <guid>strip-1</guid>
+      <link>https://comic.example/strips/1</link>
+      <pubDate>Mon, 04 Oct 2021 10:00:00 GMT</pubDate>
+      <enclosure url="https://cdn.comic.example/1.png" type="image/png" length="1000"/>
+      <media:content url="https://cdn.comic.example/1.mp4" type="video/mp4"/>
+      <description>&lt;img src="https://cdn.comic.example/1.png"&gt;</description>
+      <content:encoded><![CDATA[<p><IMG alt="Bonus" SRC='/bonus/1.jpg?size=large&amp;v=2'></p>]]></content:encoded>
+    </item>
+  </channel>
+</rss>"#;
+
+  const ATOM: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom" xmlns:media="http://search.yahoo.com/mrss/">
+  <title>Comic</title>
+  <id>urn:comic</id>
+  <updated>2021-10-04T10:00:00Z</updated>
+  <entry>
+    <id>urn:comic:2</id>
+    <title>Strip 2</title>
+    <updated>2021-10-04T10:00:00Z</updated>
+    <link rel="alternate" href="https://comic.example/strips/2"/>
+    <media:content url="https://cdn.comic.example/2.webp"/>
+    <media:content url="https://cdn.comic.example/2.html"/>
+    <content type="html">&lt;img src="panels/2.gif"&gt;</content>
+  </entry>
+</feed>"#;
+
+  fn entry_image_urls(feed: &str) -> Vec<String> {
+    let source = FeedSource::new("https://comic.example/feed".to_string());
+    let feed = feed_rs::parser::parse(feed.as_bytes()).unwrap();
+    source.image_urls(&feed.entries[0])
+  }
+
+  #[test]
+  fn rss_images_from_enclosures_and_html() {
+    assert_eq!(
+      entry_image_urls(RSS),
+      vec![
+        "https://cdn.comic.example/1.png",
+        "https://comic.example/bonus/1.jpg?size=large&v=2",
+      ]
+    );
+  }
+
+  #[test]
+  fn atom_images_from_media_and_html() {
+    assert_eq!(
+      entry_image_urls(ATOM),
+      vec![
+        "https://cdn.comic.example/2.webp",
+        "https://comic.example/strips/panels/2.gif",
+      ]
+    );
+  }
+
+  #[test]
+  fn image_extensions_ignore_query_and_case() {
+    assert!(looks_like_image("https://comic.example/1.PNG?v=2"));
+    assert!(!looks_like_image("https://comic.example/png"));
+  }
+
+  fn entry(id: u32, published: &str) -> String {
+    format!(
+      "<item><guid>strip-{0}</guid><pubDate>{1}</pubDate>\
+       <enclosure url=\"https://cdn.comic.example/{0}.png\" type=\"image/png\"/></item>",
+      id, published
+    )
+  }
+
+  fn ids(batch: &SourceBatch) -> Vec<u64> {
+    let mut ids: Vec<u64> = batch.strips.iter().map(|strip| strip.id).collect();
+    ids.sort_unstable();
+    ids
+  }
+
+  fn stable_ids(ids: &[u32]) -> Vec<u64> {
+    let mut ids: Vec<u64> = ids
+      .iter()
+      .map(|id| stable_id(&format!("strip-{}", id)))
+      .collect();
+    ids.sort_unstable();
+    ids
+  }
+
+  #[tokio::test]
+  async fn entries_sharing_the_cursor_timestamp_are_fetched_once() {
+    testing::init();
+    let entries = Arc::new(Mutex::new(vec![
+      entry(1, "Mon, 04 Oct 2021 09:00:00 GMT"),
+      entry(2, "Mon, 04 Oct 2021 10:00:00 GMT"),
+      entry(3, "Mon, 04 Oct 2021 10:00:00 GMT"),
+    ]));
+    let served = entries.clone();
+    let url = testing::serve_json(move |_| {
+      format!(
+        "<rss version=\"2.0\"><channel><title>Comic</title>{}</channel></rss>",
+        served.lock().unwrap().concat()
+      )
+    })
+    .await;
+    let source = FeedSource::new(format!("{}/feed.xml", url));
+
+    let batch = source.fetch_strips(None).await.unwrap();
+    assert_eq!(ids(&batch), stable_ids(&[1, 2, 3]));
+    let cursor = batch.max_id;
+    assert_eq!(cursor, Some(1633341600000));
+
+    // Published within the same second as the latest entry
+    entries
+      .lock()
+      .unwrap()
+      .push(entry(4, "Mon, 04 Oct 2021 10:00:00 GMT"));
+    let batch = source.fetch_strips(cursor).await.unwrap();
+    assert_eq!(ids(&batch), stable_ids(&[4]));
+    assert_eq!(batch.max_id, cursor);
+
+    entries
+      .lock()
+      .unwrap()
+      .push(entry(5, "Mon, 04 Oct 2021 11:00:00 GMT"));
+    let batch = source.fetch_strips(cursor).await.unwrap();
+    assert_eq!(ids(&batch), stable_ids(&[5]));
+    assert_eq!(batch.max_id, Some(1633345200000));
+
+    let batch = source.fetch_strips(batch.max_id).await.unwrap();
+    assert!(batch.strips.is_empty());
+  }
+}
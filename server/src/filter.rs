@@ -1,67 +1,920 @@
 use async_trait::async_trait;
-use serde::Deserialize;
-use std::sync::Arc;
+use image::ImageError;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use tract_onnx::prelude::TractError;
 
+use crate::classifier::EmbeddedClassifierFilter;
 use crate::comic_image::ComicImage;
+use crate::heuristics::{
+  AspectRatioFilter, FileSizeFilter, LineArtFilter, SizeFilter, UniformityFilter,
+};
+use crate::http;
+use crate::source::SourceError;
+use crate::STORE;
+
+const DEFAULT_MODEL_PATH: &str = "./comic_net.onnx";
+const DEFAULT_LABELS_PATH: &str = "./comic_net.labels";
+// Input size of the Mobile-Net v2 the comic_net is based on
+const DEFAULT_INPUT_SIZE: u32 = 128;
+const DEFAULT_MAX_DOMINANT_SHARE: f64 = 0.95;
+// Photos usually exceed 8 bits, as their colors spread over thousands of the
+// 4096 histogram bins.
+const DEFAULT_MAX_ENTROPY: f64 = 8.0;
+const DEFAULT_MIN_EDGE_DENSITY: f64 = 0.02;
+
+/// Outcome of filtering an image. Filters, which could not judge an image
+/// (e.g. as the classifier is unavailable), are undecided. Such images should
+/// be filtered again later on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+  Accept,
+  Reject,
+  Undecided,
+}
+
+impl From<bool> for Verdict {
+  fn from(is_valid: bool) -> Self {
+    if is_valid {
+      Verdict::Accept
+    } else {
+      Verdict::Reject
+    }
+  }
+}
+
+// Images, which can not be decoded, are rejected. Failing to read their data
+// says nothing about the image itself.
+impl From<&ImageError> for Verdict {
+  fn from(error: &ImageError) -> Self {
+    match error {
+      ImageError::IoError(_) => Verdict::Undecided,
+      _ => Verdict::Reject,
+    }
+  }
+}
+
+impl fmt::Display for Verdict {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Verdict::Accept => write!(f, "accept"),
+      Verdict::Reject => write!(f, "reject"),
+      Verdict::Undecided => write!(f, "undecided"),
+    }
+  }
+}
+
+impl FromStr for Verdict {
+  type Err = ();
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    match value {
+      "accept" => Ok(Verdict::Accept),
+      "reject" => Ok(Verdict::Reject),
+      "undecided" => Ok(Verdict::Undecided),
+      _ => Err(()),
+    }
+  }
+}
+
+/// Value measured by a filter, e.g. the probability of a label or the aspect
+/// ratio of the image
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Measure {
+  pub name: String,
+  pub value: f64,
+}
+
+impl Measure {
+  pub fn new(name: &str, value: f64) -> Self {
+    Measure {
+      name: name.to_string(),
+      value,
+    }
+  }
+}
+
+/// Verdict along with the filter of the chain, which came to it, and the
+/// measures it was based on.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Decision {
+  pub verdict: Verdict,
+  pub filter: String,
+  pub measures: Vec<Measure>,
+  /// The verdict has been taken from the verdict cache
+  pub cached: bool,
+}
+
+impl Decision {
+  pub fn new(filter: String, verdict: Verdict, measures: Vec<Measure>) -> Self {
+    Decision {
+      verdict,
+      filter,
+      measures,
+      cached: false,
+    }
+  }
+}
 
 #[async_trait]
 pub trait Filter {
-  async fn is_valid(&self, image: Arc<ComicImage>) -> bool;
+  async fn decision(&self, image: Arc<ComicImage>) -> Decision;
+
+  /// Description of the filter and its configuration. Filters with the same
+  /// identity come to the same verdict for an image.
+  fn identity(&self) -> String;
+
+  /// Ask external classifiers for the model they use, which is part of the
+  /// identity. Returns `false`, if any of them could not be reached.
+  async fn detect_model(&self) -> bool {
+    true
+  }
+}
+
+fn joined_identity(name: &str, filters: &[ImageFilter]) -> String {
+  let identities: Vec<String> = filters.iter().map(|filter| filter.identity()).collect();
+  format!("{}({})", name, identities.join(", "))
 }
 
 pub enum ImageFilter {
+  Cached(CachedFilter),
   HttpClassifier(HttpClassifierFilter),
+  EmbeddedClassifier(EmbeddedClassifierFilter),
+  AspectRatio(AspectRatioFilter),
+  Size(SizeFilter),
+  FileSize(FileSizeFilter),
+  Uniformity(UniformityFilter),
+  LineArt(LineArtFilter),
+  AllOf(Vec<ImageFilter>),
+  AnyOf(Vec<ImageFilter>),
+  Not(Box<ImageFilter>),
 }
 
 #[async_trait]
 impl Filter for ImageFilter {
-  async fn is_valid(&self, image: Arc<ComicImage>) -> bool {
+  async fn decision(&self, image: Arc<ComicImage>) -> Decision {
+    match self {
+      ImageFilter::Cached(ref filter) => filter.decision(image).await,
+      ImageFilter::HttpClassifier(ref filter) => filter.decision(image).await,
+      ImageFilter::EmbeddedClassifier(ref filter) => filter.decision(image).await,
+      ImageFilter::AspectRatio(ref filter) => filter.decision(image).await,
+      ImageFilter::Size(ref filter) => filter.decision(image).await,
+      ImageFilter::FileSize(ref filter) => filter.decision(image).await,
+      ImageFilter::Uniformity(ref filter) => filter.decision(image).await,
+      ImageFilter::LineArt(ref filter) => filter.decision(image).await,
+      // A single rejection decides, even if other filters are undecided.
+      // Otherwise the last filter agreeing with the verdict is reported.
+      ImageFilter::AllOf(ref filters) => {
+        let mut accepted = None;
+        let mut undecided = None;
+        for filter in filters {
+          let decision = filter.decision(image.clone()).await;
+          match decision.verdict {
+            Verdict::Reject => return decision,
+            Verdict::Undecided => undecided = Some(decision),
+            Verdict::Accept => accepted = Some(decision),
+          }
+        }
+        undecided
+          .or(accepted)
+          .unwrap_or_else(|| Decision::new(self.identity(), Verdict::Accept, vec![]))
+      }
+      ImageFilter::AnyOf(ref filters) => {
+        let mut rejected = None;
+        let mut undecided = None;
+        for filter in filters {
+          let decision = filter.decision(image.clone()).await;
+          match decision.verdict {
+            Verdict::Accept => return decision,
+            Verdict::Undecided => undecided = Some(decision),
+            Verdict::Reject => rejected = Some(decision),
+          }
+        }
+        undecided
+          .or(rejected)
+          .unwrap_or_else(|| Decision::new(self.identity(), Verdict::Reject, vec![]))
+      }
+      ImageFilter::Not(ref filter) => {
+        let mut decision = filter.decision(image).await;
+        decision.verdict = match decision.verdict {
+          Verdict::Accept => Verdict::Reject,
+          Verdict::Reject => Verdict::Accept,
+          Verdict::Undecided => Verdict::Undecided,
+        };
+        decision.filter = format!("not({})", decision.filter);
+        decision
+      }
+    }
+  }
+
+  fn identity(&self) -> String {
     match self {
-      ImageFilter::HttpClassifier(ref filter) => filter.is_valid(image).await,
+      ImageFilter::Cached(ref filter) => filter.identity(),
+      ImageFilter::HttpClassifier(ref filter) => filter.identity(),
+      ImageFilter::EmbeddedClassifier(ref filter) => filter.identity(),
+      ImageFilter::AspectRatio(ref filter) => filter.identity(),
+      ImageFilter::Size(ref filter) => filter.identity(),
+      ImageFilter::FileSize(ref filter) => filter.identity(),
+      ImageFilter::Uniformity(ref filter) => filter.identity(),
+      ImageFilter::LineArt(ref filter) => filter.identity(),
+      ImageFilter::AllOf(ref filters) => joined_identity("all_of", filters),
+      ImageFilter::AnyOf(ref filters) => joined_identity("any_of", filters),
+      ImageFilter::Not(ref filter) => format!("not({})", filter.identity()),
+    }
+  }
+
+  async fn detect_model(&self) -> bool {
+    match self {
+      ImageFilter::Cached(ref filter) => filter.detect_model().await,
+      ImageFilter::HttpClassifier(ref filter) => filter.detect_model().await,
+      ImageFilter::AllOf(ref filters) | ImageFilter::AnyOf(ref filters) => {
+        let mut is_detected = true;
+        for filter in filters {
+          is_detected &= filter.detect_model().await;
+        }
+        is_detected
+      }
+      ImageFilter::Not(ref filter) => filter.detect_model().await,
+      _ => true,
     }
   }
 }
 
+impl From<CachedFilter> for ImageFilter {
+  fn from(filter: CachedFilter) -> Self {
+    Self::Cached(filter)
+  }
+}
+
 impl From<HttpClassifierFilter> for ImageFilter {
   fn from(filter: HttpClassifierFilter) -> Self {
     Self::HttpClassifier(filter)
   }
 }
 
+impl From<EmbeddedClassifierFilter> for ImageFilter {
+  fn from(filter: EmbeddedClassifierFilter) -> Self {
+    Self::EmbeddedClassifier(filter)
+  }
+}
+
+impl From<AspectRatioFilter> for ImageFilter {
+  fn from(filter: AspectRatioFilter) -> Self {
+    Self::AspectRatio(filter)
+  }
+}
+
+impl From<SizeFilter> for ImageFilter {
+  fn from(filter: SizeFilter) -> Self {
+    Self::Size(filter)
+  }
+}
+
+impl From<FileSizeFilter> for ImageFilter {
+  fn from(filter: FileSizeFilter) -> Self {
+    Self::FileSize(filter)
+  }
+}
+
+impl From<UniformityFilter> for ImageFilter {
+  fn from(filter: UniformityFilter) -> Self {
+    Self::Uniformity(filter)
+  }
+}
+
+impl From<LineArtFilter> for ImageFilter {
+  fn from(filter: LineArtFilter) -> Self {
+    Self::LineArt(filter)
+  }
+}
+
+/// Declarative description of a filter chain, e.g.
+///
+/// `{"all_of": [{"classifier": {}}, {"aspect_ratio": {"min": 0.3, "max": 4}}]}`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterConfig {
+  /// Falls back to the globally configured classifier url and the default
+  /// `ClassificationRules`. The `version` overrides the model reported by the
+  /// classifier, which keeps verdicts of older models from being reused.
+  Classifier {
+    url: Option<String>,
+    version: Option<String>,
+    min_probability: Option<f64>,
+    accept: Option<Vec<String>>,
+    reject: Option<BTreeMap<String, f64>>,
+  },
+  /// ONNX export of the `comic_net`, evaluated within the server
+  EmbeddedClassifier {
+    model: Option<String>,
+    labels: Option<String>,
+    input_size: Option<u32>,
+    min_probability: Option<f64>,
+    accept: Option<Vec<String>>,
+    reject: Option<BTreeMap<String, f64>>,
+  },
+  AspectRatio {
+    min: Option<f64>,
+    max: Option<f64>,
+  },
+  Size {
+    min_width: Option<u32>,
+    min_height: Option<u32>,
+    max_width: Option<u32>,
+    max_height: Option<u32>,
+  },
+  FileSize {
+    min_bytes: u64,
+  },
+  Uniformity {
+    max_dominant_share: Option<f64>,
+  },
+  LineArt {
+    max_entropy: Option<f64>,
+    min_edge_density: Option<f64>,
+  },
+  AllOf(Vec<FilterConfig>),
+  AnyOf(Vec<FilterConfig>),
+  Not(Box<FilterConfig>),
+}
+
+#[derive(Debug)]
+pub enum FilterConfigError {
+  MissingClassifierUrl,
+  Model(TractError),
+}
+
+impl fmt::Display for FilterConfigError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      FilterConfigError::MissingClassifierUrl => write!(f, "No classifier url configured"),
+      FilterConfigError::Model(ref error) => write!(f, "model: {}", error),
+    }
+  }
+}
+
+impl std::error::Error for FilterConfigError {}
+
+impl From<TractError> for FilterConfigError {
+  fn from(error: TractError) -> Self {
+    Self::Model(error)
+  }
+}
+
+fn classification_rules(
+  min_probability: Option<f64>,
+  accept: &Option<Vec<String>>,
+  reject: &Option<BTreeMap<String, f64>>,
+) -> ClassificationRules {
+  let defaults = ClassificationRules::default();
+  ClassificationRules {
+    min_probability: min_probability.unwrap_or(defaults.min_probability),
+    accept: accept.clone().unwrap_or(defaults.accept),
+    reject: reject.clone().unwrap_or(defaults.reject),
+  }
+}
+
+impl ImageFilter {
+  /// Build the filter chain described by the config. Both classifiers are
+  /// wrapped into a `CachedFilter`, as they are by far the slowest filters.
+  /// Fails, if any of the embedded classifier models can not be loaded or a
+  /// classifier url is missing.
+  pub fn from_config(
+    config: &FilterConfig,
+    classifier_url: Option<&str>,
+  ) -> Result<Self, FilterConfigError> {
+    Ok(match config {
+      FilterConfig::Classifier {
+        ref url,
+        ref version,
+        min_probability,
+        ref accept,
+        ref reject,
+      } => {
+        let url = match (url, classifier_url) {
+          (Some(url), _) => url.clone(),
+          (None, Some(classifier_url)) => classifier_url.to_string(),
+          (None, None) => return Err(FilterConfigError::MissingClassifierUrl),
+        };
+        Self::from(CachedFilter::new(Self::from(HttpClassifierFilter::new(
+          url,
+          version.clone(),
+          classification_rules(*min_probability, accept, reject),
+        ))))
+      }
+      FilterConfig::EmbeddedClassifier {
+        ref model,
+        ref labels,
+        input_size,
+        min_probability,
+        ref accept,
+        ref reject,
+      } => Self::from(CachedFilter::new(Self::from(
+        EmbeddedClassifierFilter::load(
+          model.as_deref().unwrap_or(DEFAULT_MODEL_PATH),
+          labels.as_deref().unwrap_or(DEFAULT_LABELS_PATH),
+          input_size.unwrap_or(DEFAULT_INPUT_SIZE),
+          classification_rules(*min_probability, accept, reject),
+        )?,
+      ))),
+      FilterConfig::AspectRatio { min, max } => Self::from(AspectRatioFilter {
+        min: *min,
+        max: *max,
+      }),
+      FilterConfig::Size {
+        min_width,
+        min_height,
+        max_width,
+        max_height,
+      } => Self::from(SizeFilter {
+        min_width: *min_width,
+        min_height: *min_height,
+        max_width: *max_width,
+        max_height: *max_height,
+      }),
+      FilterConfig::FileSize { min_bytes } => Self::from(FileSizeFilter {
+        min_bytes: *min_bytes,
+      }),
+      FilterConfig::Uniformity { max_dominant_share } => Self::from(UniformityFilter {
+        max_dominant_share: max_dominant_share.unwrap_or(DEFAULT_MAX_DOMINANT_SHARE),
+      }),
+      FilterConfig::LineArt {
+        max_entropy,
+        min_edge_density,
+      } => Self::from(LineArtFilter {
+        max_entropy: max_entropy.unwrap_or(DEFAULT_MAX_ENTROPY),
+        min_edge_density: min_edge_density.unwrap_or(DEFAULT_MIN_EDGE_DENSITY),
+      }),
+      FilterConfig::AllOf(ref configs) => Self::AllOf(
+        configs
+          .iter()
+          .map(|config| Self::from_config(config, classifier_url))
+          .collect::<Result<_, _>>()?,
+      ),
+      FilterConfig::AnyOf(ref configs) => Self::AnyOf(
+        configs
+          .iter()
+          .map(|config| Self::from_config(config, classifier_url))
+          .collect::<Result<_, _>>()?,
+      ),
+      FilterConfig::Not(ref config) => {
+        Self::Not(Box::new(Self::from_config(config, classifier_url)?))
+      }
+    })
+  }
+}
+
+/// Persistent cache of the verdicts of another filter, keyed by the content
+/// hash of the image and the identity of the filter.
+pub struct CachedFilter {
+  filter: Box<ImageFilter>,
+}
+
+impl CachedFilter {
+  pub fn new(filter: ImageFilter) -> Self {
+    CachedFilter {
+      filter: Box::new(filter),
+    }
+  }
+}
+
+#[async_trait]
+impl Filter for CachedFilter {
+  async fn decision(&self, image: Arc<ComicImage>) -> Decision {
+    // The identity changes, once the model of a classifier is known.
+    let identity = self.identity();
+    let hash = image.content_hash();
+    match STORE.get().verdict(&hash, &identity) {
+      Ok(Some(is_valid)) => {
+        let verdict = Verdict::from(is_valid);
+        println!("    ? Cached verdict: {}", verdict);
+        return Decision {
+          cached: true,
+          ..Decision::new(identity, verdict, vec![])
+        };
+      }
+      Ok(None) => {}
+      Err(error) => println!("    ? Failed to read cached verdict: {}", error),
+    }
+
+    // Undecided images are not cached, to judge them again later on.
+    let decision = self.filter.decision(image).await;
+    if decision.verdict != Verdict::Undecided {
+      let is_valid = decision.verdict == Verdict::Accept;
+      if let Err(error) = STORE.get().save_verdict(&hash, &identity, is_valid) {
+        println!("    ? Failed to cache verdict: {}", error);
+      }
+    }
+    decision
+  }
+
+  fn identity(&self) -> String {
+    self.filter.identity()
+  }
+
+  async fn detect_model(&self) -> bool {
+    self.filter.detect_model().await
+  }
+}
+
+/// Rules deciding whether the scores of a classification are accepted.
+///
+/// The best scoring label has to be one of the accepted labels and reach the
+/// minimum probability. Additionally, no rejected label may reach its own
+/// threshold, regardless of its rank.
+#[derive(Debug, Clone)]
+pub struct ClassificationRules {
+  pub min_probability: f64,
+  pub accept: Vec<String>,
+  pub reject: BTreeMap<String, f64>,
+}
+
+impl Default for ClassificationRules {
+  fn default() -> Self {
+    ClassificationRules {
+      min_probability: 0.0,
+      accept: vec!["comic".to_string()],
+      reject: BTreeMap::new(),
+    }
+  }
+}
+
+impl ClassificationRules {
+  pub fn is_accepted(&self, scores: &[LabelScore]) -> bool {
+    let best = scores
+      .iter()
+      .max_by(|a, b| a.probability.total_cmp(&b.probability));
+
+    let accepted = match best {
+      Some(best) => self.accept.contains(&best.label) && best.probability >= self.min_probability,
+      None => false,
+    };
+    let rejected = scores.iter().any(|score| {
+      self
+        .reject
+        .get(&score.label)
+        .is_some_and(|threshold| score.probability >= *threshold)
+    });
+
+    accepted && !rejected
+  }
+}
+
+/// Classifier service of the `classifier` directory. Unless a `version` is
+/// configured, the model reported by the classifier is part of the identity.
 pub struct HttpClassifierFilter {
   url: String,
+  version: Option<String>,
+  model: Mutex<Option<String>>,
+  rules: ClassificationRules,
 }
 
 impl HttpClassifierFilter {
-  pub fn new(url: String) -> Self {
-    HttpClassifierFilter { url }
+  pub fn new(url: String, version: Option<String>, rules: ClassificationRules) -> Self {
+    HttpClassifierFilter {
+      url,
+      version,
+      model: Mutex::new(None),
+      rules,
+    }
+  }
+
+  fn model(&self) -> Option<String> {
+    match self.version {
+      Some(ref version) => Some(version.clone()),
+      None => self.model.lock().unwrap().clone(),
+    }
+  }
+
+  fn set_model(&self, model: &str) {
+    let mut known = self.model.lock().unwrap();
+    if known.as_deref() != Some(model) {
+      println!("Classifier uses model {}", model);
+      *known = Some(model.to_string());
+    }
   }
 }
 
+#[derive(Deserialize)]
+struct ModelInfo {
+  model: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LabelScore {
+  pub probability: f64,
+  pub label: String,
+}
+
+/// Probabilities of the labels as measures, the best scoring label first
+pub fn label_measures(scores: &[LabelScore]) -> Vec<Measure> {
+  let mut measures: Vec<Measure> = scores
+    .iter()
+    .map(|score| Measure::new(&score.label, score.probability))
+    .collect();
+  measures.sort_by(|a, b| b.value.total_cmp(&a.value));
+  measures
+}
+
+// Older classifiers only respond with the best label, newer ones add the
+// scores of all labels and the version of their model.
 #[derive(Deserialize)]
 struct Classification {
   probability: f64,
   label: String,
+  #[serde(default)]
+  scores: Vec<LabelScore>,
+  model: Option<String>,
+}
+
+impl Classification {
+  fn scores(&self) -> Vec<LabelScore> {
+    if self.scores.is_empty() {
+      vec![LabelScore {
+        probability: self.probability,
+        label: self.label.clone(),
+      }]
+    } else {
+      self.scores.clone()
+    }
+  }
 }
 
 #[async_trait]
 impl Filter for HttpClassifierFilter {
-  async fn is_valid(&self, image: Arc<ComicImage>) -> bool {
-    let client = reqwest::Client::new();
-    let request = client.post(self.url.as_str()).body(image.png_image());
-    if let Ok(response) = request.send().await {
-      if let Ok(classification) = response.json::<Classification>().await {
-        println!(
-          "    ? Classification: {} with {} probability",
-          classification.label, classification.probability
-        );
-        if classification.label == "comic" {
-          println!("    ? true");
-          return true;
-        }
+  async fn decision(&self, image: Arc<ComicImage>) -> Decision {
+    let png = match image.png_image() {
+      Ok(png) => png,
+      Err(error) => {
+        println!("    ? Unreadable image: {}", error);
+        return Decision::new(self.identity(), Verdict::from(&error), vec![]);
+      }
+    };
+
+    // Failing to reach the classifier says nothing about the image itself.
+    let request = http::client().post(self.url.as_str()).body(png);
+    let classification = match request
+      .send()
+      .await
+      .and_then(|response| response.error_for_status())
+    {
+      Ok(response) => http::read_json::<Classification>(response).await,
+      Err(error) => Err(SourceError::from(error)),
+    };
+    let classification = match classification {
+      Ok(classification) => classification,
+      Err(error) => {
+        println!("    ? Classifier failed: {}", error);
+        println!("    ? {}", Verdict::Undecided);
+        return Decision::new(self.identity(), Verdict::Undecided, vec![]);
+      }
+    };
+
+    println!(
+      "    ? Classification: {} with {} probability",
+      classification.label, classification.probability
+    );
+    match (&self.version, &classification.model) {
+      (Some(expected), Some(model)) if expected != model => println!(
+        "    ? Classifier uses model {} instead of {}",
+        model, expected
+      ),
+      (None, Some(model)) => self.set_model(model),
+      _ => {}
+    }
+    for score in classification.scores.iter() {
+      println!("    ?   {}: {}", score.label, score.probability);
+    }
+    let scores = classification.scores();
+    let verdict = Verdict::from(self.rules.is_accepted(&scores));
+    println!("    ? {}", verdict);
+    Decision::new(self.identity(), verdict, label_measures(&scores))
+  }
+
+  fn identity(&self) -> String {
+    match self.model() {
+      Some(model) => format!("classifier({}, {}, {:?})", self.url, model, self.rules),
+      None => format!("classifier({}, {:?})", self.url, self.rules),
+    }
+  }
+
+  // Classifiers without a `/model` route do not report their model at all.
+  async fn detect_model(&self) -> bool {
+    if self.model().is_some() {
+      return true;
+    }
+    let url = match Url::parse(&self.url).and_then(|url| url.join("model")) {
+      Ok(url) => url,
+      Err(error) => {
+        println!("Invalid classifier url {}: {}", self.url, error);
+        return true;
+      }
+    };
+
+    let response = match http::client().get(url).send().await {
+      Ok(response) => response,
+      Err(error) => {
+        println!("Classifier not reachable: {}", error);
+        return false;
+      }
+    };
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+      println!("Classifier does not report its model");
+      return true;
+    }
+    let model = match response.error_for_status() {
+      Ok(response) => http::read_json::<ModelInfo>(response).await,
+      Err(error) => Err(SourceError::from(error)),
+    };
+    match model {
+      Ok(model) => {
+        self.set_model(&model.model);
+        true
+      }
+      Err(error) => {
+        println!("Failed to detect classifier model: {}", error);
+        false
       }
     }
-    println!("    ? false");
-    return false;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use image::DynamicImage;
+  use std::net::TcpListener;
+
+  use super::*;
+  use crate::testing;
+
+  fn image() -> Arc<ComicImage> {
+    Arc::new(ComicImage::from(DynamicImage::new_rgb8(200, 100)))
+  }
+
+  fn accepting() -> ImageFilter {
+    ImageFilter::from(AspectRatioFilter {
+      min: Some(1.0),
+      max: None,
+    })
+  }
+
+  fn rejecting() -> ImageFilter {
+    ImageFilter::from(SizeFilter {
+      min_width: Some(400),
+      min_height: None,
+      max_width: None,
+      max_height: None,
+    })
+  }
+
+  // Classifier, which can not be reached, as nothing listens on its port
+  fn undecided() -> ImageFilter {
+    testing::init();
+    let port = TcpListener::bind("127.0.0.1:0")
+      .unwrap()
+      .local_addr()
+      .unwrap()
+      .port();
+    ImageFilter::from(HttpClassifierFilter::new(
+      format!("http://127.0.0.1:{}/", port),
+      Some("test".to_string()),
+      ClassificationRules::default(),
+    ))
+  }
+
+  async fn decide(filter: ImageFilter) -> (Verdict, String) {
+    let decision = filter.decision(image()).await;
+    (decision.verdict, decision.filter)
+  }
+
+  #[tokio::test]
+  async fn all_of_requires_every_filter_to_accept() {
+    assert_eq!(
+      decide(ImageFilter::AllOf(vec![accepting(), rejecting()])).await,
+      (Verdict::Reject, rejecting().identity())
+    );
+    assert_eq!(
+      decide(ImageFilter::AllOf(vec![rejecting(), undecided()])).await,
+      (Verdict::Reject, rejecting().identity())
+    );
+    assert_eq!(
+      decide(ImageFilter::AllOf(vec![undecided(), accepting()]))
+        .await
+        .0,
+      Verdict::Undecided
+    );
+    assert_eq!(
+      decide(ImageFilter::AllOf(vec![accepting(), accepting()])).await,
+      (Verdict::Accept, accepting().identity())
+    );
+    assert_eq!(
+      decide(ImageFilter::AllOf(vec![])).await,
+      (Verdict::Accept, "all_of()".to_string())
+    );
+  }
+
+  #[tokio::test]
+  async fn any_of_requires_a_single_filter_to_accept() {
+    assert_eq!(
+      decide(ImageFilter::AnyOf(vec![rejecting(), accepting()])).await,
+      (Verdict::Accept, accepting().identity())
+    );
+    assert_eq!(
+      decide(ImageFilter::AnyOf(vec![undecided(), accepting()])).await,
+      (Verdict::Accept, accepting().identity())
+    );
+    assert_eq!(
+      decide(ImageFilter::AnyOf(vec![rejecting(), undecided()]))
+        .await
+        .0,
+      Verdict::Undecided
+    );
+    assert_eq!(
+      decide(ImageFilter::AnyOf(vec![rejecting(), rejecting()])).await,
+      (Verdict::Reject, rejecting().identity())
+    );
+    assert_eq!(
+      decide(ImageFilter::AnyOf(vec![])).await,
+      (Verdict::Reject, "any_of()".to_string())
+    );
+  }
+
+  #[tokio::test]
+  async fn not_inverts_decided_verdicts_only() {
+    let expected = format!("not({})", accepting().identity());
+    assert_eq!(
+      decide(ImageFilter::Not(Box::new(accepting()))).await,
+      (Verdict::Reject, expected)
+    );
+    assert_eq!(
+      decide(ImageFilter::Not(Box::new(rejecting()))).await.0,
+      Verdict::Accept
+    );
+    assert_eq!(
+      decide(ImageFilter::Not(Box::new(undecided()))).await.0,
+      Verdict::Undecided
+    );
+    assert_eq!(
+      decide(ImageFilter::Not(Box::new(ImageFilter::AnyOf(vec![
+        rejecting(),
+        rejecting()
+      ]))))
+      .await
+      .0,
+      Verdict::Accept
+    );
+  }
+
+  #[test]
+  fn classifiers_require_a_url() {
+    let config: FilterConfig =
+      serde_json::from_str(r#"{"all_of": [{"size": {}}, {"classifier": {}}]}"#).unwrap();
+    assert!(matches!(
+      ImageFilter::from_config(&config, None),
+      Err(FilterConfigError::MissingClassifierUrl)
+    ));
+    let filter = ImageFilter::from_config(&config, Some("http://classifier.example/")).unwrap();
+    assert!(filter
+      .identity()
+      .contains("classifier(http://classifier.example/"));
+  }
+
+  fn scores(scores: &[(&str, f64)]) -> Vec<LabelScore> {
+    scores
+      .iter()
+      .map(|&(label, probability)| LabelScore {
+        probability,
+        label: label.to_string(),
+      })
+      .collect()
+  }
+
+  #[test]
+  fn best_label_has_to_be_accepted_with_the_min_probability() {
+    let rules = ClassificationRules {
+      min_probability: 0.6,
+      accept: vec!["comic".to_string(), "sketch".to_string()],
+      reject: BTreeMap::new(),
+    };
+    assert!(rules.is_accepted(&scores(&[("photo", 0.2), ("comic", 0.8)])));
+    assert!(rules.is_accepted(&scores(&[("sketch", 0.6), ("comic", 0.4)])));
+    assert!(!rules.is_accepted(&scores(&[("comic", 0.5), ("photo", 0.1)])));
+    assert!(!rules.is_accepted(&scores(&[("comic", 0.3), ("photo", 0.7)])));
+    assert!(!rules.is_accepted(&[]));
+  }
+
+  #[test]
+  fn rejected_labels_count_regardless_of_their_rank() {
+    let rules = ClassificationRules {
+      reject: vec![("text".to_string(), 0.25)].into_iter().collect(),
+      ..ClassificationRules::default()
+    };
+    assert!(rules.is_accepted(&scores(&[("comic", 0.8), ("text", 0.2)])));
+    assert!(!rules.is_accepted(&scores(&[("comic", 0.7), ("text", 0.25)])));
   }
 }
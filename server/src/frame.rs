@@ -0,0 +1,47 @@
+use chrono::{DateTime, Utc};
+use image::DynamicImage;
+use rocket::http::ContentType;
+use rocket::response::content;
+use serde::{Deserialize, Serialize};
+
+use crate::image_data;
+
+/// Amount of the latest frames kept to render them again
+pub const FRAME_HISTORY: usize = 100;
+
+/// Strip shown within a frame
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FrameStrip {
+  pub source: String,
+  pub strip_id: u64,
+}
+
+/// Composition created for a device. Its image is kept along with it, so
+/// every rendition shows the very same composition. The shown strips are
+/// kept in the order they have been laid out.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Frame {
+  pub id: u64,
+  pub device: String,
+  pub seed: u64,
+  pub created_at: DateTime<Utc>,
+  #[serde(default)]
+  pub strips: Vec<FrameStrip>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Rendition {
+  Color,
+  Grayscale,
+  Inkplate,
+}
+
+impl Rendition {
+  pub fn render(self, image: &DynamicImage) -> content::Custom<Vec<u8>> {
+    match self {
+      Rendition::Color => content::Custom(ContentType::PNG, image_data::png(image)),
+      Rendition::Grayscale => content::Custom(ContentType::PNG, image_data::inkplate_png(image)),
+      Rendition::Inkplate => content::Custom(ContentType::Binary, image_data::inkplate_raw(image)),
+    }
+  }
+}
@@ -0,0 +1,229 @@
+use async_trait::async_trait;
+use image::DynamicImage;
+use std::sync::Arc;
+
+use crate::comic_image::ComicImage;
+use crate::filter::{Decision, Filter, Measure, Verdict};
+
+// Analyses of the image content are performed on a thumbnail, as they only
+// need a rough impression of the image.
+const ANALYSIS_SIZE: u32 = 256;
+// Minimal sobel gradient magnitude of a pixel to be counted as an edge
+const EDGE_THRESHOLD: u16 = 256;
+
+// Decode and analyse the image on the blocking thread pool. Images, which can
+// not be decoded, are rejected, while unreadable ones stay undecided.
+async fn analyze<T, F>(image: Arc<ComicImage>, analysis: F) -> Result<T, Verdict>
+where
+  F: FnOnce(&DynamicImage) -> T + Send + 'static,
+  T: Send + 'static,
+{
+  let result = tokio::task::spawn_blocking(move || {
+    image
+      .dynamic_image()
+      .map(|decoded| analysis(&decoded.thumbnail(ANALYSIS_SIZE, ANALYSIS_SIZE)))
+  })
+  .await;
+
+  match result {
+    Ok(Ok(value)) => Ok(value),
+    Ok(Err(error)) => {
+      println!("    ? Unreadable image: {}", error);
+      Err(Verdict::from(&error))
+    }
+    Err(error) => {
+      println!("    ? Analysis failed: {}", error);
+      Err(Verdict::Undecided)
+    }
+  }
+}
+
+// Occurrences of every color, reduced to 4 bits per channel
+fn color_histogram(image: &DynamicImage) -> Vec<u32> {
+  let mut histogram = vec![0u32; 4096];
+  for pixel in image.to_rgb8().pixels() {
+    let [r, g, b] = pixel.0;
+    let bin = ((r as usize >> 4) << 8) | ((g as usize >> 4) << 4) | (b as usize >> 4);
+    histogram[bin] += 1;
+  }
+  histogram
+}
+
+fn entropy(histogram: &[u32]) -> f64 {
+  let total: u32 = histogram.iter().sum();
+  histogram
+    .iter()
+    .filter(|count| **count > 0)
+    .map(|count| {
+      let probability = *count as f64 / total as f64;
+      -probability * probability.log2()
+    })
+    .sum()
+}
+
+fn edge_density(image: &DynamicImage) -> f64 {
+  let gradients = imageproc::gradients::sobel_gradients(&image.to_luma8());
+  let edges = gradients
+    .pixels()
+    .filter(|pixel| pixel.0[0] >= EDGE_THRESHOLD)
+    .count();
+  edges as f64 / (gradients.width() * gradients.height()) as f64
+}
+
+/// Accepts images, whose width divided by their height is within the bounds.
+pub struct AspectRatioFilter {
+  pub min: Option<f64>,
+  pub max: Option<f64>,
+}
+
+#[async_trait]
+impl Filter for AspectRatioFilter {
+  async fn decision(&self, image: Arc<ComicImage>) -> Decision {
+    let aspect_ratio = image.width() as f64 / image.height() as f64;
+    let is_valid = self.min.map_or(true, |min| aspect_ratio >= min)
+      && self.max.map_or(true, |max| aspect_ratio <= max);
+    println!("    ? Aspect ratio {:.2}: {}", aspect_ratio, is_valid);
+    Decision::new(
+      self.identity(),
+      Verdict::from(is_valid),
+      vec![Measure::new("aspect_ratio", aspect_ratio)],
+    )
+  }
+
+  fn identity(&self) -> String {
+    format!("aspect_ratio({:?}, {:?})", self.min, self.max)
+  }
+}
+
+/// Accepts images with dimensions within the bounds.
+pub struct SizeFilter {
+  pub min_width: Option<u32>,
+  pub min_height: Option<u32>,
+  pub max_width: Option<u32>,
+  pub max_height: Option<u32>,
+}
+
+#[async_trait]
+impl Filter for SizeFilter {
+  async fn decision(&self, image: Arc<ComicImage>) -> Decision {
+    let (width, height) = image.dimensions();
+    let is_valid = self.min_width.map_or(true, |min| width >= min)
+      && self.min_height.map_or(true, |min| height >= min)
+      && self.max_width.map_or(true, |max| width <= max)
+      && self.max_height.map_or(true, |max| height <= max);
+    println!("    ? Size {}x{}: {}", width, height, is_valid);
+    Decision::new(
+      self.identity(),
+      Verdict::from(is_valid),
+      vec![
+        Measure::new("width", width as f64),
+        Measure::new("height", height as f64),
+      ],
+    )
+  }
+
+  fn identity(&self) -> String {
+    format!(
+      "size({:?}, {:?}, {:?}, {:?})",
+      self.min_width, self.min_height, self.max_width, self.max_height
+    )
+  }
+}
+
+/// Accepts images with at least the given size of their encoded data.
+/// Tiny files usually are icons, placeholders or blank images.
+pub struct FileSizeFilter {
+  pub min_bytes: u64,
+}
+
+#[async_trait]
+impl Filter for FileSizeFilter {
+  async fn decision(&self, image: Arc<ComicImage>) -> Decision {
+    let size = image.data_size();
+    let is_valid = size >= self.min_bytes;
+    println!("    ? File size {} bytes: {}", size, is_valid);
+    Decision::new(
+      self.identity(),
+      Verdict::from(is_valid),
+      vec![Measure::new("file_size", size as f64)],
+    )
+  }
+
+  fn identity(&self) -> String {
+    format!("file_size({})", self.min_bytes)
+  }
+}
+
+/// Rejects blank or almost blank images, in which a single color covers more
+/// than the given share of the image.
+pub struct UniformityFilter {
+  pub max_dominant_share: f64,
+}
+
+#[async_trait]
+impl Filter for UniformityFilter {
+  async fn decision(&self, image: Arc<ComicImage>) -> Decision {
+    let dominant_share = match analyze(image, |image| {
+      let histogram = color_histogram(image);
+      let total: u32 = histogram.iter().sum();
+      *histogram.iter().max().unwrap_or(&0) as f64 / total.max(1) as f64
+    })
+    .await
+    {
+      Ok(dominant_share) => dominant_share,
+      Err(verdict) => return Decision::new(self.identity(), verdict, vec![]),
+    };
+
+    let is_valid = dominant_share <= self.max_dominant_share;
+    println!("    ? Dominant color {:.2}: {}", dominant_share, is_valid);
+    Decision::new(
+      self.identity(),
+      Verdict::from(is_valid),
+      vec![Measure::new("dominant_share", dominant_share)],
+    )
+  }
+
+  fn identity(&self) -> String {
+    format!("uniformity({})", self.max_dominant_share)
+  }
+}
+
+/// Accepts line art rather than photos. Drawings use few colors, resulting in
+/// a low entropy of their color histogram, and consist of a lot of sharp
+/// edges.
+pub struct LineArtFilter {
+  pub max_entropy: f64,
+  pub min_edge_density: f64,
+}
+
+#[async_trait]
+impl Filter for LineArtFilter {
+  async fn decision(&self, image: Arc<ComicImage>) -> Decision {
+    let (color_entropy, edge_density) = match analyze(image, |image| {
+      (entropy(&color_histogram(image)), edge_density(image))
+    })
+    .await
+    {
+      Ok(measures) => measures,
+      Err(verdict) => return Decision::new(self.identity(), verdict, vec![]),
+    };
+
+    let is_valid = color_entropy <= self.max_entropy && edge_density >= self.min_edge_density;
+    println!(
+      "    ? Color entropy {:.2}, edge density {:.3}: {}",
+      color_entropy, edge_density, is_valid
+    );
+    Decision::new(
+      self.identity(),
+      Verdict::from(is_valid),
+      vec![
+        Measure::new("entropy", color_entropy),
+        Measure::new("edge_density", edge_density),
+      ],
+    )
+  }
+
+  fn identity(&self) -> String {
+    format!("line_art({}, {})", self.max_entropy, self.min_edge_density)
+  }
+}
@@ -0,0 +1,77 @@
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Client, Response};
+use serde::de::DeserializeOwned;
+use std::time::Duration;
+
+use crate::source::SourceError;
+use crate::{CONFIG, HTTP};
+
+/// Create the client shared by all outgoing requests of the server.
+pub fn build_client(connect_timeout: u64, timeout: u64) -> reqwest::Result<Client> {
+  Client::builder()
+    .connect_timeout(Duration::from_secs(connect_timeout))
+    .timeout(Duration::from_secs(timeout))
+    .build()
+}
+
+pub fn client() -> &'static Client {
+  HTTP.get()
+}
+
+fn max_download_size() -> usize {
+  CONFIG.get().max_download_mib * 1024 * 1024
+}
+
+/// Read the body of a response, without ever holding more than the
+/// configured maximum download size in memory.
+pub async fn read_body(mut response: Response) -> Result<Vec<u8>, SourceError> {
+  let max_size = max_download_size();
+  if let Some(length) = response.content_length() {
+    if length > max_size as u64 {
+      return Err(SourceError::InvalidResponse(format!(
+        "{} exceeds the download limit with {} bytes",
+        response.url(),
+        length
+      )));
+    }
+  }
+
+  let mut body = vec![];
+  while let Some(chunk) = response.chunk().await? {
+    if body.len() + chunk.len() > max_size {
+      return Err(SourceError::InvalidResponse(format!(
+        "{} exceeds the download limit",
+        response.url()
+      )));
+    }
+    body.extend_from_slice(&chunk);
+  }
+
+  Ok(body)
+}
+
+/// Read a JSON response, which is subject to the download limit as well.
+pub async fn read_json<T: DeserializeOwned>(response: Response) -> Result<T, SourceError> {
+  let url = response.url().to_string();
+  let body = read_body(response).await?;
+  serde_json::from_slice(&body)
+    .map_err(|error| SourceError::InvalidResponse(format!("{}: {}", url, error)))
+}
+
+/// Download an image, rejecting every response, which announces itself as
+/// something else.
+pub async fn download_image(url: &str) -> Result<Vec<u8>, SourceError> {
+  let response = client().get(url).send().await?.error_for_status()?;
+
+  if let Some(content_type) = response.headers().get(CONTENT_TYPE) {
+    let content_type = content_type.to_str().unwrap_or("");
+    if !content_type.starts_with("image/") && content_type != "application/octet-stream" {
+      return Err(SourceError::InvalidResponse(format!(
+        "{} is not an image, but {}",
+        url, content_type
+      )));
+    }
+  }
+
+  read_body(response).await
+}
@@ -50,7 +50,7 @@ pub fn inkplate_raw(image: &DynamicImage) -> Vec<u8> {
         current_byte = p & 0xf0
       } else {
         // Second of two pixels (low nible)
-        current_byte = current_byte | (p >> 4);
+        current_byte |= p >> 4;
 
         // Write finished byte
         out_bytes.push(current_byte);
@@ -0,0 +1,173 @@
+use chrono::{DateTime, Utc};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::str::FromStr;
+
+use crate::audit::{DecisionQuery, DecisionRecord};
+use crate::{BLOBS, STORE};
+
+/// Classes of the training data set of the `comic_net`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Label {
+  Comic,
+  NoComic,
+}
+
+impl FromStr for Label {
+  type Err = ();
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    match value {
+      "comic" => Ok(Label::Comic),
+      "no_comic" => Ok(Label::NoComic),
+      _ => Err(()),
+    }
+  }
+}
+
+impl Label {
+  /// Name of the class, as used by the folders of the training images
+  pub fn name(&self) -> &'static str {
+    match self {
+      Label::Comic => "comic",
+      Label::NoComic => "no_comic",
+    }
+  }
+}
+
+/// Manual classification of an image, identified by its blob hash.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageLabel {
+  pub label: Label,
+  pub url: String,
+  pub labeled_at: DateTime<Utc>,
+}
+
+/// Recently judged image, as offered for labeling
+#[derive(Debug, Serialize)]
+pub struct LabelingCandidate {
+  #[serde(flatten)]
+  pub record: DecisionRecord,
+  pub label: Option<Label>,
+}
+
+/// Protect the images of all labels from being evicted from the blob cache,
+/// as they are part of the exported training data.
+pub fn pin_labeled_images() -> sled::Result<()> {
+  let labels = STORE.get().labels()?;
+  let missing = labels
+    .iter()
+    .filter(|(hash, _)| !BLOBS.get().pin(hash))
+    .count();
+  if missing > 0 {
+    println!("{} labeled images are no longer cached", missing);
+  }
+  Ok(())
+}
+
+/// Recently judged images, which are still available within the blob cache,
+/// newest first. Every image is only listed once per page.
+pub fn labeling_candidates(
+  before: Option<u64>,
+  limit: usize,
+) -> sled::Result<(Vec<LabelingCandidate>, Option<u64>)> {
+  let store = STORE.get();
+  let query = DecisionQuery::default();
+
+  let mut candidates: Vec<LabelingCandidate> = vec![];
+  let mut before = before;
+  loop {
+    let records = store.decisions(&query, before, limit)?;
+    let is_exhausted = records.len() < limit;
+    before = records.last().map(|record| record.id);
+
+    for record in records {
+      let hash = match record.hash {
+        Some(ref hash) => hash.clone(),
+        None => continue,
+      };
+      if !BLOBS.get().contains(&hash)
+        || candidates
+          .iter()
+          .any(|candidate| candidate.record.hash.as_deref() == Some(hash.as_str()))
+      {
+        continue;
+      }
+
+      let label = store.label(&hash)?.map(|label| label.label);
+      candidates.push(LabelingCandidate { record, label });
+      if candidates.len() >= limit {
+        let next = candidates.last().map(|candidate| candidate.record.id);
+        return Ok((candidates, next));
+      }
+    }
+
+    if is_exhausted {
+      return Ok((candidates, None));
+    }
+  }
+}
+
+// Name of the image within the archive. The hash keeps it unique, even if
+// urls of different sources end with the same file name.
+fn image_file_name(hash: &str, data: &[u8]) -> String {
+  let extension = image::guess_format(data)
+    .ok()
+    .and_then(|format| format.extensions_str().first())
+    .unwrap_or(&"bin");
+  format!("{}.{}", hash, extension)
+}
+
+fn append_file<W: io::Write>(
+  archive: &mut tar::Builder<W>,
+  path: &str,
+  data: &[u8],
+) -> io::Result<()> {
+  let mut header = tar::Header::new_gnu();
+  header.set_size(data.len() as u64);
+  header.set_mode(0o644);
+  header.set_mtime(Utc::now().timestamp() as u64);
+  header.set_cksum();
+  archive.append_data(&mut header, path, data)
+}
+
+/// Gzipped tar archive of all labeled images in the layout of
+/// `classifier/training`: `urls_comic.log` and `urls_no_comic.log` list the
+/// downloadable urls, `images/comic` and `images/no_comic` contain the images
+/// still available within the blob cache.
+pub fn export_archive() -> io::Result<Vec<u8>> {
+  let labels = STORE.get().labels()?;
+
+  let mut archive = tar::Builder::new(GzEncoder::new(vec![], Compression::default()));
+  for label in [Label::Comic, Label::NoComic] {
+    let mut urls = String::new();
+    for (hash, image_label) in labels.iter().filter(|(_, entry)| entry.label == label) {
+      if image_label.url.starts_with("http://") || image_label.url.starts_with("https://") {
+        urls.push_str(&image_label.url);
+        urls.push('\n');
+      }
+
+      // Evicted images are only part of the url log
+      let data = match BLOBS.get().handle(hash).map(|handle| handle.read()) {
+        Some(Ok(data)) => data,
+        Some(Err(error)) => {
+          println!("Skipping unreadable labeled image {}: {}", hash, error);
+          continue;
+        }
+        None => continue,
+      };
+      let path = format!("images/{}/{}", label.name(), image_file_name(hash, &data));
+      append_file(&mut archive, &path, &data)?;
+    }
+    append_file(
+      &mut archive,
+      &format!("urls_{}.log", label.name()),
+      urls.as_bytes(),
+    )?;
+  }
+
+  archive.into_inner()?.finish()
+}
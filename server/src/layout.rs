@@ -1,3 +1,6 @@
+// The cassowary constraint DSL relies on `|` binding weaker than arithmetic.
+#![allow(clippy::precedence)]
+
 use std::sync::Arc;
 
 use cassowary::strength::{MEDIUM, REQUIRED, STRONG};
@@ -96,10 +99,24 @@ pub struct RowLayout {
   secondary: Vec<Arc<ComicImage>>,
 }
 
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum StripArrangement {
+  Row,
+  Stack,
+  Grid,
+}
+
+pub struct StripLayout {
+  margin: f64,
+  images: Vec<Arc<ComicImage>>,
+  arrangement: StripArrangement,
+}
+
 pub enum Layout {
   Single(SingleLayout),
   Column(ColumnLayout),
   Row(RowLayout),
+  Strip(StripLayout),
 }
 
 impl SingleLayout {
@@ -304,7 +321,7 @@ impl CalculateLayout for ColumnLayout {
     for (index, secondary_box) in secondary_boxes.iter().enumerate() {
       instructions.push(DrawingInstruction::new(
         self.secondary[index].clone(),
-        &secondary_box,
+        secondary_box,
       ));
     }
 
@@ -465,7 +482,7 @@ impl CalculateLayout for RowLayout {
     for (index, secondary_box) in secondary_boxes.iter().enumerate() {
       instructions.push(DrawingInstruction::new(
         self.secondary[index].clone(),
-        &secondary_box,
+        secondary_box,
       ));
     }
 
@@ -473,12 +490,146 @@ impl CalculateLayout for RowLayout {
   }
 }
 
+impl StripLayout {
+  /// Arrange all images in the way covering most of the available space.
+  pub fn new_with_margin(images: Vec<Arc<ComicImage>>, margin: f64) -> Self {
+    let mut candidates = vec![StripArrangement::Row, StripArrangement::Stack];
+    if images.len() > 2 {
+      candidates.push(StripArrangement::Grid);
+    }
+
+    let mut best = Self::new_with_arrangement(images.clone(), margin, StripArrangement::Row);
+    let mut best_area = 0.0;
+    for arrangement in candidates {
+      let candidate = Self::new_with_arrangement(images.clone(), margin, arrangement);
+      let area: f64 = candidate.panels().iter().map(|(_, _, w, h)| w * h).sum();
+      if area > best_area {
+        best_area = area;
+        best = candidate;
+      }
+    }
+
+    best
+  }
+
+  pub fn new_with_arrangement(
+    images: Vec<Arc<ComicImage>>,
+    margin: f64,
+    arrangement: StripArrangement,
+  ) -> Self {
+    Self {
+      margin,
+      images,
+      arrangement,
+    }
+  }
+
+  fn grid_size(&self) -> (usize, usize) {
+    let count = self.images.len();
+    let columns = (count as f64).sqrt().ceil() as usize;
+    let rows = count.div_ceil(columns);
+    (columns, rows)
+  }
+
+  // Position (x, y, w, h) of every image in reading order relative to the
+  // upper left corner of the arrangement.
+  fn panels(&self) -> Vec<(f64, f64, f64, f64)> {
+    let count = self.images.len() as f64;
+    let available_width = MAX_WIDTH - self.margin * 2.0;
+    let available_height = MAX_HEIGHT - self.margin * 2.0;
+    let mut panels = vec![];
+
+    match self.arrangement {
+      StripArrangement::Row => {
+        // All images share the same height
+        let aspect_sum: f64 = self.images.iter().map(aspect_ratio).sum();
+        let height =
+          available_height.min((available_width - self.margin * (count - 1.0)) / aspect_sum);
+        let mut x = 0.0;
+        for image in self.images.iter() {
+          let width = height * aspect_ratio(image);
+          panels.push((x, 0.0, width, height));
+          x += width + self.margin;
+        }
+      }
+      StripArrangement::Stack => {
+        // All images share the same width
+        let inverse_aspect_sum: f64 = self.images.iter().map(|i| 1.0 / aspect_ratio(i)).sum();
+        let width = available_width
+          .min((available_height - self.margin * (count - 1.0)) / inverse_aspect_sum);
+        let mut y = 0.0;
+        for image in self.images.iter() {
+          let height = width / aspect_ratio(image);
+          panels.push((0.0, y, width, height));
+          y += height + self.margin;
+        }
+      }
+      StripArrangement::Grid => {
+        // Uniform cells based on the average aspect ratio, each image is
+        // centered within its cell.
+        let (columns, rows) = self.grid_size();
+        let (columns, rows) = (columns as f64, rows as f64);
+        let cell_aspect = self.images.iter().map(aspect_ratio).sum::<f64>() / count;
+        let cell_width = ((available_width - self.margin * (columns - 1.0)) / columns)
+          .min((available_height - self.margin * (rows - 1.0)) / rows * cell_aspect);
+        let cell_height = cell_width / cell_aspect;
+        for (index, image) in self.images.iter().enumerate() {
+          let column = (index % columns as usize) as f64;
+          let row = (index / columns as usize) as f64;
+          let image_aspect = aspect_ratio(image);
+          let (width, height) = if image_aspect > cell_aspect {
+            (cell_width, cell_width / image_aspect)
+          } else {
+            (cell_height * image_aspect, cell_height)
+          };
+          panels.push((
+            column * (cell_width + self.margin) + (cell_width - width) / 2.0,
+            row * (cell_height + self.margin) + (cell_height - height) / 2.0,
+            width,
+            height,
+          ));
+        }
+      }
+    }
+
+    panels
+  }
+}
+
+impl CalculateLayout for StripLayout {
+  fn calculate(&self) -> Vec<DrawingInstruction> {
+    let panels = self.panels();
+
+    // Center the whole arrangement within the given space
+    let total_width = panels.iter().map(|(x, _, w, _)| x + w).fold(0.0, f64::max);
+    let total_height = panels.iter().map(|(_, y, _, h)| y + h).fold(0.0, f64::max);
+    let offset_x = (MAX_WIDTH - total_width) / 2.0;
+    let offset_y = (MAX_HEIGHT - total_height) / 2.0;
+
+    println!("StripLayout Arrangement: {:?}", self.arrangement);
+
+    self
+      .images
+      .iter()
+      .zip(panels.iter())
+      .map(|(image, (x, y, w, h))| DrawingInstruction {
+        image: image.clone(),
+        x: (offset_x + x).floor() as u32,
+        y: (offset_y + y).floor() as u32,
+        w: w.floor() as u32,
+        h: h.floor() as u32,
+      })
+      .collect()
+  }
+}
+
 impl CalculateLayout for Layout {
   fn calculate(&self) -> Vec<DrawingInstruction> {
     match self {
       Layout::Single(ref single_layout) => single_layout.calculate(),
       Layout::Column(ref column_layout) => column_layout.calculate(),
       Layout::Row(ref row_layout) => row_layout.calculate(),
+      Layout::Strip(ref strip_layout) => strip_layout.calculate(),
     }
   }
 }
@@ -500,3 +651,105 @@ impl From<RowLayout> for Layout {
     Self::Row(inner)
   }
 }
+
+impl From<StripLayout> for Layout {
+  fn from(inner: StripLayout) -> Self {
+    Self::Strip(inner)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use image::DynamicImage;
+
+  use super::*;
+
+  const MARGIN: f64 = 8.0;
+
+  fn images(sizes: &[(u32, u32)]) -> Vec<Arc<ComicImage>> {
+    sizes
+      .iter()
+      .map(|&(width, height)| Arc::new(ComicImage::from(DynamicImage::new_rgb8(width, height))))
+      .collect()
+  }
+
+  // Panels have to keep the aspect ratio of their image, stay within the
+  // available space and keep at least the margin between each other.
+  fn assert_valid(layout: &StripLayout) -> Vec<(f64, f64, f64, f64)> {
+    let panels = layout.panels();
+    assert_eq!(panels.len(), layout.images.len());
+    for (image, &(x, y, w, h)) in layout.images.iter().zip(panels.iter()) {
+      assert!((w / h - aspect_ratio(image)).abs() < 1e-6);
+      assert!(x >= 0.0 && y >= 0.0);
+      assert!(x + w <= MAX_WIDTH - MARGIN * 2.0 + 1e-6);
+      assert!(y + h <= MAX_HEIGHT - MARGIN * 2.0 + 1e-6);
+    }
+    for (index, &(x, y, w, h)) in panels.iter().enumerate() {
+      for &(other_x, other_y, other_w, other_h) in &panels[index + 1..] {
+        let apart_horizontally =
+          x + w + MARGIN <= other_x + 1e-6 || other_x + other_w + MARGIN <= x + 1e-6;
+        let apart_vertically =
+          y + h + MARGIN <= other_y + 1e-6 || other_y + other_h + MARGIN <= y + 1e-6;
+        assert!(apart_horizontally || apart_vertically);
+      }
+    }
+    panels
+  }
+
+  #[test]
+  fn row_panels_share_their_height() {
+    let layout = StripLayout::new_with_arrangement(
+      images(&[(300, 400), (600, 400), (300, 400)]),
+      MARGIN,
+      StripArrangement::Row,
+    );
+    let panels = assert_valid(&layout);
+    assert!(panels
+      .iter()
+      .all(|&(_, y, _, h)| y == 0.0 && h == panels[0].3));
+    // The width is the limit, so the row spans all of it
+    let (x, _, w, _) = panels[2];
+    assert!((x + w - (MAX_WIDTH - MARGIN * 2.0)).abs() < 1e-6);
+  }
+
+  #[test]
+  fn stack_panels_share_their_width() {
+    let layout = StripLayout::new_with_arrangement(
+      images(&[(800, 200), (800, 400)]),
+      MARGIN,
+      StripArrangement::Stack,
+    );
+    let panels = assert_valid(&layout);
+    assert!(panels
+      .iter()
+      .all(|&(x, _, w, _)| x == 0.0 && w == panels[0].2));
+    // The height is the limit, so the stack spans all of it
+    let (_, y, _, h) = panels[1];
+    assert!((y + h - (MAX_HEIGHT - MARGIN * 2.0)).abs() < 1e-6);
+  }
+
+  #[test]
+  fn grid_panels_fill_rows_in_reading_order() {
+    let layout = StripLayout::new_with_arrangement(
+      images(&[(400, 400), (400, 400), (400, 400), (400, 400), (400, 400)]),
+      MARGIN,
+      StripArrangement::Grid,
+    );
+    assert_eq!(layout.grid_size(), (3, 2));
+    let panels = assert_valid(&layout);
+    assert!(panels[0].0 < panels[1].0 && panels[1].0 < panels[2].0);
+    assert_eq!(panels[0].1, panels[2].1);
+    assert_eq!(panels[3].0, panels[0].0);
+    assert!(panels[3].1 > panels[0].1);
+  }
+
+  #[test]
+  fn arrangement_covering_most_space_is_chosen() {
+    let wide = StripLayout::new_with_margin(images(&[(800, 200), (800, 200)]), MARGIN);
+    assert_eq!(wide.arrangement, StripArrangement::Stack);
+    let tall = StripLayout::new_with_margin(images(&[(200, 800), (200, 800)]), MARGIN);
+    assert_eq!(tall.arrangement, StripArrangement::Row);
+    let square = StripLayout::new_with_margin(images(&[(400, 400); 4]), MARGIN);
+    assert_eq!(square.arrangement, StripArrangement::Grid);
+  }
+}
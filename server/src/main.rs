@@ -1,22 +1,69 @@
+mod audit;
+mod auth;
+mod blob_cache;
+mod classifier;
+mod collection;
+mod comic_image;
 mod composition;
+mod directory;
 mod dithering;
-mod layout;
-mod twitter;
+mod feed;
 mod filter;
-mod comic_image;
+mod frame;
+mod heuristics;
+mod http;
 mod image_data;
+mod labeling;
+mod layout;
+mod manual;
+mod mastodon;
+mod moderation;
+mod selection;
+mod source;
+mod store;
+#[cfg(test)]
+mod testing;
+mod twitter;
+mod upload;
 
-use composition::create_composition_image;
+use audit::DecisionQuery;
+use auth::{AdminToken, UploadToken};
+use blob_cache::BlobCache;
+use chrono::Utc;
+use collection::{comic_refresh_task, UserComicCollection};
+use composition::{create_composition, seeded_composition};
+use directory::DirectorySource;
 use egg_mode::user::UserID;
 use egg_mode::Token;
-use rand::seq::SliceRandom;
-use rocket::http::ContentType;
-use rocket::response::content;
+use feed::FeedSource;
+use filter::{FilterConfig, ImageFilter, Verdict};
+use frame::{Frame, Rendition, FRAME_HISTORY};
+use image::DynamicImage;
+use labeling::{ImageLabel, Label};
+use manual::ManualSource;
+use mastodon::MastodonSource;
+use moderation::{Moderation, ModerationEntry};
+use rocket::data::{Data, Limits, ToByteUnit};
+use rocket::form::Form;
+use rocket::http::{ContentType, Header, Status};
+use rocket::response::{content, status, Redirect};
+use rocket::{Responder, State};
+use selection::{Selection, SelectionConfig, Selector};
 use serde::Deserialize;
+use source::{ComicSource, Source};
+use std::path::PathBuf;
 use std::sync::Arc;
+use store::ComicStore;
 use tokio::sync::Mutex;
-use twitter::ComicStrip;
-use twitter::{access_token, twitter_refresh_task, UserComicCollection};
+use twitter::{access_token, TwitterSource};
+use upload::ComicUpload;
+
+const DECISIONS_PAGE_SIZE: usize = 50;
+const DECISIONS_MAX_PAGE_SIZE: usize = 500;
+const LABELING_PAGE_SIZE: usize = 24;
+const MAX_DEVICE_NAME_LENGTH: usize = 64;
+const SEED_HEADER: &str = "X-Comic-Seed";
+const FRAME_ID_HEADER: &str = "X-Frame-Id";
 
 #[derive(Deserialize, Debug)]
 struct Config {
@@ -25,8 +72,82 @@ struct Config {
   access_token: String,
   access_token_secret: String,
   twitter_usernames: Vec<String>,
+  #[serde(default)]
+  feed_urls: Vec<String>,
+  #[serde(default)]
+  mastodon_accounts: Vec<String>,
+  #[serde(default)]
+  comic_directories: Vec<String>,
   twitter_refresh_interval: u64,
-  http_classifier_url: String,
+  http_classifier_url: Option<String>,
+  upload_token: Option<String>,
+  admin_token: Option<String>,
+  /// JSON encoded `FilterConfig`, defaults to the http classifier alone
+  filter: Option<String>,
+  /// JSON encoded `SelectionConfig`, defaults to the fair rotation
+  selection: Option<String>,
+  #[serde(default = "default_store_path")]
+  store_path: String,
+  #[serde(default = "default_blob_cache_path")]
+  blob_cache_path: String,
+  #[serde(default = "default_blob_cache_max_mib")]
+  blob_cache_max_mib: u64,
+  #[serde(default = "default_http_connect_timeout")]
+  http_connect_timeout: u64,
+  #[serde(default = "default_http_timeout")]
+  http_timeout: u64,
+  #[serde(default = "default_max_download_mib")]
+  max_download_mib: usize,
+  #[serde(default = "default_max_image_dimension")]
+  max_image_dimension: u32,
+  #[serde(default = "default_pending_max_attempts")]
+  pending_max_attempts: u32,
+  #[serde(default = "default_decision_log_days")]
+  decision_log_days: i64,
+  /// Devices with a history of their own, the least recently used ones are
+  /// forgotten
+  #[serde(default = "default_max_devices")]
+  max_devices: usize,
+}
+
+fn default_store_path() -> String {
+  "./comic_store".to_string()
+}
+
+fn default_blob_cache_path() -> String {
+  "./blob_cache".to_string()
+}
+
+fn default_blob_cache_max_mib() -> u64 {
+  512
+}
+
+fn default_http_connect_timeout() -> u64 {
+  10
+}
+
+fn default_http_timeout() -> u64 {
+  60
+}
+
+fn default_max_download_mib() -> usize {
+  20
+}
+
+fn default_max_image_dimension() -> u32 {
+  12000
+}
+
+fn default_pending_max_attempts() -> u32 {
+  5
+}
+
+fn default_max_devices() -> usize {
+  20
+}
+
+fn default_decision_log_days() -> i64 {
+  30
 }
 
 fn env_config() -> Config {
@@ -36,72 +157,599 @@ fn env_config() -> Config {
   }
 }
 
-pub async fn random_comic_strips() -> Vec<Arc<ComicStrip>> {
-  let collections = COLLECTION_ARC.get();
-  let mut collection_refs: Vec<&Mutex<UserComicCollection>> = (*collections).iter().collect();
-  collection_refs.shuffle(&mut rand::thread_rng());
+// Devices identify themselves by name, devices without one share a history
+fn device_name(device: Option<String>) -> Result<String, status::Custom<String>> {
+  let device = device.unwrap_or_default();
+  let is_valid = device.len() <= MAX_DEVICE_NAME_LENGTH
+    && device
+      .chars()
+      .all(|c| c.is_ascii_alphanumeric() || "-_.:".contains(c));
+  if !is_valid {
+    return Err(status::Custom(
+      Status::BadRequest,
+      format!("Invalid device {:?}", device),
+    ));
+  }
+  Ok(device)
+}
+
+/// Rendition of a frame along with its id and the seed it has been composed
+/// from
+#[derive(Responder)]
+struct FrameRendition {
+  inner: content::Custom<Vec<u8>>,
+  id: Header<'static>,
+  seed: Header<'static>,
+}
+
+impl FrameRendition {
+  fn new(frame: &Frame, rendition: Rendition, image: &DynamicImage) -> Self {
+    FrameRendition {
+      inner: rendition.render(image),
+      id: Header::new(FRAME_ID_HEADER, frame.id.to_string()),
+      seed: Header::new(SEED_HEADER, frame.seed.to_string()),
+    }
+  }
+}
+
+// Compositions with an explicit seed do not depend on the history of the
+// device, so they are not added to it either.
+async fn new_frame(
+  device: Option<String>,
+  seed: Option<u64>,
+  rendition: Rendition,
+) -> Result<FrameRendition, status::Custom<String>> {
+  let device = device_name(device)?;
+  let created_at = Utc::now();
+  let (seed, composition) = match seed {
+    Some(seed) => (seed, seeded_composition(seed, created_at)),
+    None => {
+      let seed = rand::random();
+      (seed, create_composition(&device, seed, created_at))
+    }
+  };
+  let composition = composition.ok_or_else(|| {
+    status::Custom(
+      Status::ServiceUnavailable,
+      "No comic strips available yet".to_string(),
+    )
+  })?;
 
-  for shuffled_ref in collection_refs {
-    let locked_collection = shuffled_ref.lock().await;
-    if locked_collection.comic_strips.len() == 0 {
-      continue;
+  let mut frame = Frame {
+    id: 0,
+    device,
+    seed,
+    created_at,
+    strips: composition.strips,
+  };
+  frame.id = STORE
+    .get()
+    .save_frame(
+      frame.clone(),
+      &image_data::png(&composition.image),
+      FRAME_HISTORY,
+    )
+    .map_err(|error| status::Custom(Status::InternalServerError, error.to_string()))?;
+  Ok(FrameRendition::new(&frame, rendition, &composition.image))
+}
+
+fn stored_frame(id: u64, rendition: Rendition) -> Result<FrameRendition, status::Custom<String>> {
+  let (frame, png) = match STORE.get().frame(id) {
+    Ok(Some(frame)) => frame,
+    Ok(None) => {
+      return Err(status::Custom(
+        Status::NotFound,
+        format!("Unknown frame {}", id),
+      ))
+    }
+    Err(error) => {
+      return Err(status::Custom(
+        Status::InternalServerError,
+        error.to_string(),
+      ))
     }
+  };
+  let image = image::load_from_memory(&png)
+    .map_err(|error| status::Custom(Status::InternalServerError, error.to_string()))?;
+  Ok(FrameRendition::new(&frame, rendition, &image))
+}
+
+#[rocket::get("/comic/color?<device>&<seed>")]
+async fn comic_color(
+  device: Option<String>,
+  seed: Option<u64>,
+) -> Result<FrameRendition, status::Custom<String>> {
+  new_frame(device, seed, Rendition::Color).await
+}
+
+#[rocket::get("/comic/grayscale?<device>&<seed>")]
+async fn comic_grayscale(
+  device: Option<String>,
+  seed: Option<u64>,
+) -> Result<FrameRendition, status::Custom<String>> {
+  new_frame(device, seed, Rendition::Grayscale).await
+}
+
+#[rocket::get("/comic/inkplate?<device>&<seed>")]
+async fn comic_inkplate(
+  device: Option<String>,
+  seed: Option<u64>,
+) -> Result<FrameRendition, status::Custom<String>> {
+  new_frame(device, seed, Rendition::Inkplate).await
+}
 
-    let mut strips = locked_collection.comic_strips.clone();
-    strips.shuffle(&mut rand::thread_rng());
-    return strips;
+#[rocket::get("/frame/<id>/color")]
+fn frame_color(id: u64) -> Result<FrameRendition, status::Custom<String>> {
+  stored_frame(id, Rendition::Color)
+}
+
+#[rocket::get("/frame/<id>/grayscale")]
+fn frame_grayscale(id: u64) -> Result<FrameRendition, status::Custom<String>> {
+  stored_frame(id, Rendition::Grayscale)
+}
+
+#[rocket::get("/frame/<id>/inkplate")]
+fn frame_inkplate(id: u64) -> Result<FrameRendition, status::Custom<String>> {
+  stored_frame(id, Rendition::Inkplate)
+}
+
+#[rocket::get("/frame/latest?<device>")]
+fn frame_latest(device: Option<String>) -> Result<Redirect, status::Custom<String>> {
+  let device = device_name(device)?;
+  match STORE.get().latest_frame(&device) {
+    Ok(Some(frame)) => Ok(Redirect::to(format!("/frame/{}/color", frame.id))),
+    Ok(None) => Err(status::Custom(
+      Status::NotFound,
+      format!("No frame for device {:?}", device),
+    )),
+    Err(error) => Err(status::Custom(
+      Status::InternalServerError,
+      error.to_string(),
+    )),
   }
+}
+
+#[rocket::post("/comics", format = "multipart/form-data", data = "<upload>")]
+async fn upload_comic_form(
+  _token: UploadToken,
+  upload: Form<ComicUpload>,
+  filter: &State<Arc<ImageFilter>>,
+) -> Result<status::Custom<String>, status::Custom<String>> {
+  let upload = upload.into_inner();
+  upload::add_manual_strip(
+    upload.image.0,
+    upload.author,
+    upload.title,
+    upload.url,
+    upload.filter.unwrap_or(false).then(|| filter.as_ref()),
+  )
+  .await
+}
 
-  return vec![];
+#[rocket::post("/comics?<author>&<title>&<url>&<filter>", data = "<data>", rank = 2)]
+async fn upload_comic_raw(
+  _token: UploadToken,
+  author: Option<String>,
+  title: Option<String>,
+  url: Option<String>,
+  filter: Option<bool>,
+  data: Data<'_>,
+  image_filter: &State<Arc<ImageFilter>>,
+) -> Result<status::Custom<String>, status::Custom<String>> {
+  let bytes = match data
+    .open(upload::UPLOAD_LIMIT_MIB.mebibytes())
+    .into_bytes()
+    .await
+  {
+    Ok(bytes) if bytes.is_complete() => bytes.into_inner(),
+    Ok(_) => {
+      return Err(status::Custom(
+        Status::PayloadTooLarge,
+        "Image exceeds the upload limit".to_string(),
+      ))
+    }
+    Err(error) => return Err(status::Custom(Status::BadRequest, error.to_string())),
+  };
+
+  let image_filter = filter.unwrap_or(false).then(|| image_filter.as_ref());
+  upload::add_manual_strip(bytes, author, title, url, image_filter).await
 }
 
+#[rocket::delete("/filter/cache")]
+async fn clear_filter_cache(_token: AdminToken) -> status::Custom<String> {
+  // Per url classifications are cleared as well, otherwise already known
+  // images would never reach the filters again.
+  let store = STORE.get();
+  match store
+    .clear_verdicts()
+    .and_then(|removed| store.clear_classifications().map(|_| removed))
+  {
+    Ok(removed) => status::Custom(Status::Ok, format!("Removed {} cached verdicts", removed)),
+    Err(error) => status::Custom(Status::InternalServerError, error.to_string()),
+  }
+}
 
-#[rocket::get("/comic/color")]
-async fn comic_color() -> Option<content::Custom<Vec<u8>>> {
-  return Some(content::Custom(
-    ContentType::PNG,
-    image_data::png(&create_composition_image().await),
-  ));
+#[rocket::delete("/filter/cache/<hash>")]
+async fn remove_filter_cache_entry(_token: AdminToken, hash: &str) -> status::Custom<String> {
+  match STORE.get().remove_verdicts(hash) {
+    Ok(0) => status::Custom(Status::NotFound, format!("No cached verdicts for {}", hash)),
+    Ok(removed) => status::Custom(Status::Ok, format!("Removed {} cached verdicts", removed)),
+    Err(error) => status::Custom(Status::InternalServerError, error.to_string()),
+  }
 }
 
-#[rocket::get("/comic/grayscale")]
-async fn comic_grayscale() -> Option<content::Custom<Vec<u8>>> {
-  return Some(content::Custom(
-    ContentType::PNG,
-    image_data::inkplate_png(&create_composition_image().await),
-  ));
+#[rocket::get("/filter/pending")]
+async fn pending_strips(_token: AdminToken) -> content::Json<String> {
+  let mut pending = vec![];
+  for collection_mut in COLLECTION_ARC.get().iter() {
+    let collection = collection_mut.lock().await;
+    for strip in collection.pending.iter() {
+      pending.push(serde_json::json!({
+        "source": collection.source.identifier(),
+        "id": strip.id,
+        "created_at": strip.created_at,
+        "image_urls": strip.image_urls,
+        "attempts": strip.attempts,
+      }));
+    }
+  }
+
+  content::Json(serde_json::Value::from(pending).to_string())
+}
+
+#[rocket::get("/filter/decisions?<source>&<strip>&<label>&<verdict>&<before>&<limit>")]
+async fn filter_decisions(
+  _token: AdminToken,
+  source: Option<String>,
+  strip: Option<u64>,
+  label: Option<String>,
+  verdict: Option<&str>,
+  before: Option<u64>,
+  limit: Option<usize>,
+) -> Result<content::Json<String>, status::Custom<String>> {
+  let verdict = match verdict {
+    Some(verdict) => match verdict.parse::<Verdict>() {
+      Ok(verdict) => Some(verdict),
+      Err(_) => {
+        return Err(status::Custom(
+          Status::BadRequest,
+          format!("Unknown verdict {}", verdict),
+        ))
+      }
+    },
+    None => None,
+  };
+  let query = DecisionQuery {
+    source,
+    strip_id: strip,
+    label,
+    verdict,
+  };
+  let limit = limit
+    .unwrap_or(DECISIONS_PAGE_SIZE)
+    .min(DECISIONS_MAX_PAGE_SIZE);
+
+  match STORE.get().decisions(&query, before, limit) {
+    Ok(decisions) => {
+      // The next page starts below the last returned record
+      let next = match decisions.last() {
+        Some(last) if decisions.len() == limit => Some(last.id),
+        _ => None,
+      };
+      Ok(content::Json(
+        serde_json::json!({ "decisions": decisions, "next": next }).to_string(),
+      ))
+    }
+    Err(error) => Err(status::Custom(
+      Status::InternalServerError,
+      error.to_string(),
+    )),
+  }
+}
+
+#[rocket::get("/labeling")]
+async fn labeling_page() -> content::Html<&'static str> {
+  content::Html(include_str!("labeling.html"))
+}
+
+#[rocket::get("/labeling/images?<before>")]
+async fn labeling_images(
+  _token: AdminToken,
+  before: Option<u64>,
+) -> Result<content::Json<String>, status::Custom<String>> {
+  match labeling::labeling_candidates(before, LABELING_PAGE_SIZE) {
+    Ok((images, next)) => Ok(content::Json(
+      serde_json::json!({ "images": images, "next": next }).to_string(),
+    )),
+    Err(error) => Err(status::Custom(
+      Status::InternalServerError,
+      error.to_string(),
+    )),
+  }
+}
+
+#[rocket::get("/images/<hash>")]
+async fn cached_image(_token: AdminToken, hash: &str) -> Option<content::Custom<Vec<u8>>> {
+  let data = BLOBS.get().handle(hash)?.read().ok()?;
+  let content_type = image::guess_format(&data)
+    .ok()
+    .and_then(|format| format.extensions_str().first())
+    .and_then(|extension| ContentType::from_extension(extension))
+    .unwrap_or(ContentType::Binary);
+  Some(content::Custom(content_type, data))
+}
+
+#[rocket::put("/labels/<hash>?<label>&<url>")]
+async fn label_image(
+  _token: AdminToken,
+  hash: &str,
+  label: &str,
+  url: String,
+) -> status::Custom<String> {
+  let label = match label.parse::<Label>() {
+    Ok(label) => label,
+    Err(_) => return status::Custom(Status::BadRequest, format!("Unknown label {}", label)),
+  };
+  if !BLOBS.get().pin(hash) {
+    return status::Custom(Status::NotFound, format!("Unknown image {}", hash));
+  }
+
+  let image_label = ImageLabel {
+    label,
+    url,
+    labeled_at: Utc::now(),
+  };
+  match STORE.get().save_label(hash, &image_label) {
+    Ok(_) => status::Custom(Status::Ok, format!("Labeled {} as {}", hash, label.name())),
+    Err(error) => {
+      BLOBS.get().unpin(hash);
+      status::Custom(Status::InternalServerError, error.to_string())
+    }
+  }
+}
+
+#[rocket::delete("/labels/<hash>")]
+async fn remove_image_label(_token: AdminToken, hash: &str) -> status::Custom<String> {
+  match STORE.get().remove_label(hash) {
+    Ok(true) => {
+      BLOBS.get().unpin(hash);
+      status::Custom(Status::Ok, format!("Removed label of {}", hash))
+    }
+    Ok(false) => status::Custom(Status::NotFound, format!("No label for {}", hash)),
+    Err(error) => status::Custom(Status::InternalServerError, error.to_string()),
+  }
+}
+
+#[rocket::get("/labels/export")]
+async fn export_labels(
+  _token: AdminToken,
+) -> Result<content::Custom<Vec<u8>>, status::Custom<String>> {
+  match tokio::task::spawn_blocking(labeling::export_archive).await {
+    Ok(Ok(archive)) => Ok(content::Custom(ContentType::GZIP, archive)),
+    Ok(Err(error)) => Err(status::Custom(
+      Status::InternalServerError,
+      error.to_string(),
+    )),
+    Err(error) => Err(status::Custom(
+      Status::InternalServerError,
+      error.to_string(),
+    )),
+  }
+}
+
+#[rocket::get("/moderation")]
+async fn moderation_entries(_token: AdminToken) -> content::Json<String> {
+  content::Json(serde_json::to_string(&Moderation::load()).unwrap())
+}
+
+fn save_moderation(entry: ModerationEntry) -> status::Custom<String> {
+  match STORE.get().save_moderation(&entry) {
+    Ok(_) => status::Custom(Status::Ok, format!("Added {:?}", entry)),
+    Err(error) => status::Custom(Status::InternalServerError, error.to_string()),
+  }
 }
 
-#[rocket::get("/comic/inkplate")]
-async fn comic_inkplate() -> Option<Vec<u8>> {
-  return Some(image_data::inkplate_raw(&create_composition_image().await));
+fn remove_moderation(entry: ModerationEntry) -> status::Custom<String> {
+  match STORE.get().remove_moderation(&entry) {
+    Ok(true) => status::Custom(Status::Ok, format!("Removed {:?}", entry)),
+    Ok(false) => status::Custom(Status::NotFound, format!("No such entry {:?}", entry)),
+    Err(error) => status::Custom(Status::InternalServerError, error.to_string()),
+  }
+}
+
+#[rocket::put("/moderation/hidden/<id>?<source>")]
+async fn hide_strip(_token: AdminToken, source: String, id: u64) -> status::Custom<String> {
+  save_moderation(ModerationEntry::Hidden(source, id))
+}
+
+#[rocket::delete("/moderation/hidden/<id>?<source>")]
+async fn unhide_strip(_token: AdminToken, source: String, id: u64) -> status::Custom<String> {
+  remove_moderation(ModerationEntry::Hidden(source, id))
+}
+
+// Strips rejected before are added right away, instead of waiting for the
+// next reclassification.
+#[rocket::put("/moderation/included/<id>?<source>")]
+async fn include_strip(
+  _token: AdminToken,
+  source: String,
+  id: u64,
+  filter: &State<Arc<ImageFilter>>,
+) -> status::Custom<String> {
+  let saved = save_moderation(ModerationEntry::Included(source.clone(), id));
+  if saved.0 != Status::Ok {
+    return saved;
+  }
+
+  match collection::include_rejected_strip(COLLECTION_ARC.get(), filter, &source, id).await {
+    Some(true) => status::Custom(Status::Ok, format!("Included strip {}", id)),
+    Some(false) => saved,
+    None => status::Custom(
+      Status::ServiceUnavailable,
+      format!("Could not load the images of strip {}, try again later", id),
+    ),
+  }
+}
+
+#[rocket::delete("/moderation/included/<id>?<source>")]
+async fn uninclude_strip(_token: AdminToken, source: String, id: u64) -> status::Custom<String> {
+  remove_moderation(ModerationEntry::Included(source, id))
+}
+
+#[rocket::put("/moderation/blocked/<hash>")]
+async fn block_image(_token: AdminToken, hash: &str) -> status::Custom<String> {
+  save_moderation(ModerationEntry::Blocked(hash.to_string()))
+}
+
+#[rocket::delete("/moderation/blocked/<hash>")]
+async fn unblock_image(_token: AdminToken, hash: &str) -> status::Custom<String> {
+  remove_moderation(ModerationEntry::Blocked(hash.to_string()))
 }
 
 static CONFIG: state::Storage<Config> = state::Storage::new();
 static TOKEN: state::Storage<Token> = state::Storage::new();
+static STORE: state::Storage<ComicStore> = state::Storage::new();
+static BLOBS: state::Storage<BlobCache> = state::Storage::new();
+static HTTP: state::Storage<reqwest::Client> = state::Storage::new();
 static COLLECTION_ARC: state::Storage<Arc<Vec<Mutex<UserComicCollection>>>> = state::Storage::new();
+static SELECTOR: state::Storage<Selector> = state::Storage::new();
 
 #[tokio::main]
 async fn main() {
   CONFIG.set(env_config());
   TOKEN.set(access_token());
+  HTTP.set(
+    match http::build_client(CONFIG.get().http_connect_timeout, CONFIG.get().http_timeout) {
+      Ok(client) => client,
+      Err(error) => panic!("Could not create http client: {}", error),
+    },
+  );
+  BLOBS.set(
+    match BlobCache::open(
+      PathBuf::from(&CONFIG.get().blob_cache_path),
+      CONFIG.get().blob_cache_max_mib * 1024 * 1024,
+    ) {
+      Ok(blobs) => blobs,
+      Err(error) => panic!("Could not open blob cache: {}", error),
+    },
+  );
+  STORE.set(match ComicStore::open(&CONFIG.get().store_path) {
+    Ok(store) => store,
+    Err(error) => panic!("Could not open comic store: {}", error),
+  });
+  if let Err(error) = labeling::pin_labeled_images() {
+    println!("Failed to load labels: {}", error);
+  }
 
-  let mut user_collections = vec![];
+  let mut user_collections = vec![Mutex::new(UserComicCollection::new(Source::from(
+    ManualSource,
+  )))];
 
   for twittername in CONFIG.get().twitter_usernames.iter() {
-    user_collections.push(Mutex::new(UserComicCollection::new(UserID::ScreenName(
-      twittername.into(),
+    user_collections.push(Mutex::new(UserComicCollection::new(Source::from(
+      TwitterSource::new(UserID::ScreenName(twittername.into())),
     ))));
   }
 
+  for feed_url in CONFIG.get().feed_urls.iter() {
+    user_collections.push(Mutex::new(UserComicCollection::new(Source::from(
+      FeedSource::new(feed_url.clone()),
+    ))));
+  }
+
+  for account in CONFIG.get().mastodon_accounts.iter() {
+    let source = match MastodonSource::from_account(account) {
+      Some(source) => source,
+      None => panic!("Invalid mastodon account: {}", account),
+    };
+    user_collections.push(Mutex::new(UserComicCollection::new(Source::from(source))));
+  }
+
+  for directory in CONFIG.get().comic_directories.iter() {
+    user_collections.push(Mutex::new(UserComicCollection::new(Source::from(
+      DirectorySource::new(PathBuf::from(directory)),
+    ))));
+  }
+
+  for collection_mut in user_collections.iter_mut() {
+    collection_mut.get_mut().restore();
+  }
+
   COLLECTION_ARC.set(Arc::new(user_collections));
 
-  tokio::spawn(twitter_refresh_task(COLLECTION_ARC.get().clone()));
+  let selection_config = match CONFIG.get().selection {
+    Some(ref selection) => match serde_json::from_str::<SelectionConfig>(selection) {
+      Ok(config) => config,
+      Err(error) => panic!("Invalid selection configuration: {}", error),
+    },
+    None => SelectionConfig::default(),
+  };
+  SELECTOR.set(Selector::new(
+    Selection::from(&selection_config),
+    CONFIG.get().max_devices,
+  ));
+
+  let filter_config = match CONFIG.get().filter {
+    Some(ref filter) => match serde_json::from_str::<FilterConfig>(filter) {
+      Ok(config) => config,
+      Err(error) => panic!("Invalid filter configuration: {}", error),
+    },
+    None => FilterConfig::Classifier {
+      url: None,
+      version: None,
+      min_probability: None,
+      accept: None,
+      reject: None,
+    },
+  };
+  let filter =
+    match ImageFilter::from_config(&filter_config, CONFIG.get().http_classifier_url.as_deref()) {
+      Ok(filter) => Arc::new(filter),
+      Err(error) => panic!("Could not create filter: {}", error),
+    };
+
+  tokio::spawn(comic_refresh_task(
+    COLLECTION_ARC.get().clone(),
+    filter.clone(),
+  ));
+
+  let figment = rocket::Config::figment().merge((
+    "limits",
+    Limits::default().limit("data-form", upload::UPLOAD_LIMIT_MIB.mebibytes()),
+  ));
 
-  rocket::build()
+  rocket::custom(figment)
+    .manage(filter)
     .mount(
       "/",
-      rocket::routes![comic_color, comic_grayscale, comic_inkplate],
+      rocket::routes![
+        comic_color,
+        comic_grayscale,
+        comic_inkplate,
+        frame_color,
+        frame_grayscale,
+        frame_inkplate,
+        frame_latest,
+        upload_comic_form,
+        upload_comic_raw,
+        clear_filter_cache,
+        remove_filter_cache_entry,
+        pending_strips,
+        filter_decisions,
+        labeling_page,
+        labeling_images,
+        cached_image,
+        label_image,
+        remove_image_label,
+        export_labels,
+        moderation_entries,
+        hide_strip,
+        unhide_strip,
+        include_strip,
+        uninclude_strip,
+        block_image,
+        unblock_image
+      ],
     )
     .launch()
     .await
@@ -0,0 +1,30 @@
+use async_trait::async_trait;
+
+use crate::source::{ComicSource, SourceBatch, SourceError};
+
+/// Strips added by hand through the upload endpoint.
+///
+/// There is nothing to fetch from this source. Its collection is only ever
+/// modified directly.
+#[derive(Debug, Clone)]
+pub struct ManualSource;
+
+#[async_trait]
+impl ComicSource for ManualSource {
+  fn identifier(&self) -> String {
+    "manual".to_string()
+  }
+
+  async fn fetch_strips(&self, max_id: Option<u64>) -> Result<SourceBatch, SourceError> {
+    Ok(SourceBatch {
+      strips: vec![],
+      max_id,
+      exhaustive: false,
+    })
+  }
+
+  // Uploads only pass the filter on request
+  fn is_filtered(&self) -> bool {
+    false
+  }
+}
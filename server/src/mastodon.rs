@@ -0,0 +1,341 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+use crate::http;
+use crate::source::{ComicSource, Popularity, SourceBatch, SourceError, SourceStrip};
+
+// Maximum allowed page size of the mastodon api
+const PAGE_SIZE: usize = 40;
+// Upper bound of statuses loaded during one fetch (similar to the twitter timeline)
+const MAX_STATUSES: usize = 200;
+// Maximum amount of statuses looked up at once
+const LOOKUP_SIZE: usize = 20;
+
+#[derive(Deserialize)]
+struct Account {
+  id: String,
+}
+
+#[derive(Deserialize)]
+struct MediaAttachment {
+  #[serde(rename = "type")]
+  media_type: String,
+  url: String,
+}
+
+#[derive(Deserialize)]
+struct Status {
+  id: String,
+  created_at: DateTime<Utc>,
+  media_attachments: Vec<MediaAttachment>,
+  #[serde(default)]
+  favourites_count: u64,
+  #[serde(default)]
+  reblogs_count: u64,
+}
+
+impl Status {
+  fn popularity(&self) -> Popularity {
+    Popularity {
+      likes: self.favourites_count,
+      reposts: self.reblogs_count,
+    }
+  }
+}
+
+/// Public statuses of a single Mastodon account.
+#[derive(Debug, Clone)]
+pub struct MastodonSource {
+  instance_url: String,
+  username: String,
+}
+
+impl MastodonSource {
+  pub fn new(instance_url: String, username: String) -> Self {
+    MastodonSource {
+      instance_url: instance_url.trim_end_matches('/').to_string(),
+      username,
+    }
+  }
+
+  /// Create a source from an account in `username@instance` notation.
+  ///
+  /// The instance is contacted via https, unless an explicit scheme is given
+  /// (`username@http://localhost:3000`).
+  pub fn from_account(account: &str) -> Option<Self> {
+    let account = account.trim_start_matches('@');
+    let (username, instance) = account.split_once('@')?;
+    if username.is_empty() || instance.is_empty() {
+      return None;
+    }
+
+    let instance_url = if instance.starts_with("http://") || instance.starts_with("https://") {
+      instance.to_string()
+    } else {
+      format!("https://{}", instance)
+    };
+
+    Some(Self::new(instance_url, username.to_string()))
+  }
+
+  async fn account_id(&self, client: &reqwest::Client) -> Result<String, SourceError> {
+    let response = client
+      .get(format!("{}/api/v1/accounts/lookup", self.instance_url))
+      .query(&[("acct", self.username.as_str())])
+      .send()
+      .await?
+      .error_for_status()?;
+    let account: Account = http::read_json(response).await?;
+
+    Ok(account.id)
+  }
+
+  async fn statuses(
+    &self,
+    client: &reqwest::Client,
+    account_id: &str,
+    min_id: Option<u64>,
+    max_id: Option<u64>,
+  ) -> Result<Vec<Status>, SourceError> {
+    let mut query = vec![
+      ("only_media", "true".to_string()),
+      ("exclude_reblogs", "true".to_string()),
+      ("limit", PAGE_SIZE.to_string()),
+    ];
+    if let Some(min_id) = min_id {
+      query.push(("min_id", min_id.to_string()));
+    }
+    if let Some(max_id) = max_id {
+      query.push(("max_id", max_id.to_string()));
+    }
+
+    let response = client
+      .get(format!(
+        "{}/api/v1/accounts/{}/statuses",
+        self.instance_url, account_id
+      ))
+      .query(&query)
+      .send()
+      .await?
+      .error_for_status()?;
+
+    http::read_json(response).await
+  }
+}
+
+#[async_trait]
+impl ComicSource for MastodonSource {
+  fn identifier(&self) -> String {
+    format!("mastodon:{}@{}", self.username, self.instance_url)
+  }
+
+  async fn fetch_strips(&self, max_id: Option<u64>) -> Result<SourceBatch, SourceError> {
+    let client = http::client();
+    let account_id = self.account_id(client).await?;
+
+    // Without any known status, older pages are requested starting at the
+    // newest one. Otherwise newer pages are requested starting right after
+    // the last known status (`max_id` of the collection), so no status is
+    // skipped, once more than `MAX_STATUSES` are new. The remaining ones
+    // follow on the next fetch.
+    let mut statuses: Vec<Status> = vec![];
+    let mut page_min_id = max_id;
+    let mut page_max_id: Option<u64> = None;
+    while statuses.len() < MAX_STATUSES {
+      let page = self
+        .statuses(client, &account_id, page_min_id, page_max_id)
+        .await?;
+      if page.is_empty() {
+        break;
+      }
+
+      let page_len = page.len();
+      let ids: Vec<u64> = page
+        .iter()
+        .filter_map(|status| status.id.parse().ok())
+        .collect();
+      match max_id {
+        Some(_) => page_min_id = ids.iter().max().copied(),
+        None => page_max_id = ids.iter().min().copied(),
+      }
+      statuses.extend(page);
+
+      if page_len < PAGE_SIZE || ids.is_empty() {
+        break;
+      }
+    }
+
+    println!("Received {} statuses: processing...", statuses.len());
+
+    let mut new_max_id = max_id;
+    let mut strips = vec![];
+    for status in statuses {
+      let id: u64 = match status.id.parse() {
+        Ok(id) => id,
+        Err(_) => continue,
+      };
+
+      new_max_id = Some(new_max_id.map_or(id, |max_id| max_id.max(id)));
+
+      let popularity = status.popularity();
+      let image_urls = status
+        .media_attachments
+        .into_iter()
+        .filter(|attachment| attachment.media_type == "image")
+        .map(|attachment| attachment.url)
+        .collect();
+
+      strips.push(SourceStrip {
+        id,
+        created_at: status.created_at,
+        image_urls,
+        popularity: Some(popularity),
+      });
+    }
+
+    Ok(SourceBatch {
+      strips,
+      max_id: new_max_id,
+      exhaustive: false,
+    })
+  }
+
+  // Requires Mastodon 4.3, older instances keep the initial reactions.
+  async fn fetch_popularity(&self, ids: &[u64]) -> Result<Vec<(u64, Popularity)>, SourceError> {
+    let client = http::client();
+    let mut popularities = vec![];
+    for chunk in ids.chunks(LOOKUP_SIZE) {
+      let query: Vec<(&str, String)> = chunk.iter().map(|id| ("id[]", id.to_string())).collect();
+      let response = client
+        .get(format!("{}/api/v1/statuses", self.instance_url))
+        .query(&query)
+        .send()
+        .await?
+        .error_for_status()?;
+      let statuses: Vec<Status> = http::read_json(response).await?;
+      popularities.extend(
+        statuses
+          .iter()
+          .filter_map(|status| Some((status.id.parse().ok()?, status.popularity()))),
+      );
+    }
+    Ok(popularities)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use reqwest::Url;
+  use serde_json::json;
+
+  use super::*;
+  use crate::testing;
+
+  // Instance with the statuses 1 to `count` of a single account, answering
+  // like Mastodon: `max_id` pages downwards from the newest status below it,
+  // `min_id` upwards from the status right above it, both newest first.
+  async fn instance(count: u64) -> MastodonSource {
+    let url = testing::serve_json(move |url: &Url| {
+      if url.path() == "/api/v1/accounts/lookup" {
+        return json!({ "id": "7" }).to_string();
+      }
+      assert_eq!(url.path(), "/api/v1/accounts/7/statuses");
+
+      let query = |name: &str| {
+        url
+          .query_pairs()
+          .find(|(key, _)| key == name)
+          .map(|(_, value)| value.parse::<u64>().unwrap())
+      };
+      let limit = query("limit").unwrap();
+      let mut ids: Vec<u64> = match (query("min_id"), query("max_id")) {
+        (Some(min_id), _) => (min_id + 1..=count).take(limit as usize).collect(),
+        (None, Some(max_id)) => (1..max_id).rev().take(limit as usize).collect(),
+        (None, None) => (1..=count).rev().take(limit as usize).collect(),
+      };
+      ids.sort_unstable_by(|a, b| b.cmp(a));
+
+      let statuses: Vec<_> = ids
+        .iter()
+        .map(|id| {
+          json!({
+            "id": id.to_string(),
+            "created_at": "2021-10-04T10:00:00.000Z",
+            "media_attachments": [
+              { "type": "image", "url": format!("https://files.example/{}.png", id) },
+              { "type": "video", "url": format!("https://files.example/{}.mp4", id) },
+            ],
+            "favourites_count": id * 2,
+            "reblogs_count": id,
+          })
+        })
+        .collect();
+      json!(statuses).to_string()
+    })
+    .await;
+    MastodonSource::new(url, "artist".to_string())
+  }
+
+  fn ids(batch: &SourceBatch) -> Vec<u64> {
+    let mut ids: Vec<u64> = batch.strips.iter().map(|strip| strip.id).collect();
+    ids.sort_unstable();
+    ids
+  }
+
+  #[test]
+  fn accounts_default_to_https() {
+    let source = MastodonSource::from_account("@artist@comics.example").unwrap();
+    assert_eq!(
+      source.identifier(),
+      "mastodon:artist@https://comics.example"
+    );
+    let source = MastodonSource::from_account("artist@http://localhost:3000/").unwrap();
+    assert_eq!(source.identifier(), "mastodon:artist@http://localhost:3000");
+    assert!(MastodonSource::from_account("artist").is_none());
+  }
+
+  #[tokio::test]
+  async fn first_fetch_pages_down_from_the_newest_status() {
+    testing::init();
+    let source = instance(100).await;
+
+    let batch = source.fetch_strips(None).await.unwrap();
+    assert_eq!(ids(&batch), (1..=100).collect::<Vec<u64>>());
+    assert_eq!(batch.max_id, Some(100));
+
+    let strip = batch.strips.iter().find(|strip| strip.id == 42).unwrap();
+    assert_eq!(strip.image_urls, vec!["https://files.example/42.png"]);
+    assert_eq!(
+      strip.popularity,
+      Some(Popularity {
+        likes: 84,
+        reposts: 42
+      })
+    );
+  }
+
+  #[tokio::test]
+  async fn later_fetches_page_up_from_the_cursor_without_skipping() {
+    testing::init();
+    let source = instance(300).await;
+
+    let batch = source.fetch_strips(Some(10)).await.unwrap();
+    assert_eq!(
+      ids(&batch),
+      (11..=10 + MAX_STATUSES as u64).collect::<Vec<u64>>()
+    );
+    assert_eq!(batch.max_id, Some(10 + MAX_STATUSES as u64));
+
+    let batch = source.fetch_strips(batch.max_id).await.unwrap();
+    assert_eq!(
+      ids(&batch),
+      (11 + MAX_STATUSES as u64..=300).collect::<Vec<u64>>()
+    );
+    assert_eq!(batch.max_id, Some(300));
+
+    let batch = source.fetch_strips(batch.max_id).await.unwrap();
+    assert!(batch.strips.is_empty());
+    assert_eq!(batch.max_id, Some(300));
+  }
+}
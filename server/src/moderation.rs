@@ -0,0 +1,118 @@
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use crate::collection::ComicStrip;
+use crate::STORE;
+
+/// A single manual decision, overriding the filters. Strips are referenced by
+/// the identifier of their source and their id, as ids are only unique within
+/// a source.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ModerationEntry {
+  /// Strip, which is never shown, regardless of its images
+  Hidden(String, u64),
+  /// Strip, of which every image is accepted without asking the filter
+  Included(String, u64),
+  /// Image content (by blob hash), which is never shown in any strip
+  Blocked(String),
+}
+
+/// Snapshot of all manual decisions.
+///
+/// Hidden strips and blocked images are skipped by the collection refresh
+/// and are removed from the selection of strips to show, so they disappear
+/// from already collected strips as well. Included strips bypass the filters
+/// and rejected ones are added to their collection right away.
+#[derive(Debug, Default, Serialize)]
+pub struct Moderation {
+  pub hidden: BTreeSet<(String, u64)>,
+  pub included: BTreeSet<(String, u64)>,
+  pub blocked: BTreeSet<String>,
+}
+
+impl Moderation {
+  /// Current decisions from the comic store. Without them, nothing is
+  /// moderated.
+  pub fn load() -> Self {
+    STORE.get().moderation().unwrap_or_else(|error| {
+      println!("Failed to load moderation: {}", error);
+      Moderation::default()
+    })
+  }
+
+  pub fn insert(&mut self, entry: ModerationEntry) {
+    match entry {
+      ModerationEntry::Hidden(source, id) => self.hidden.insert((source, id)),
+      ModerationEntry::Included(source, id) => self.included.insert((source, id)),
+      ModerationEntry::Blocked(hash) => self.blocked.insert(hash),
+    };
+  }
+
+  pub fn is_hidden(&self, source: &str, id: u64) -> bool {
+    self.hidden.contains(&(source.to_string(), id))
+  }
+
+  pub fn is_included(&self, source: &str, id: u64) -> bool {
+    self.included.contains(&(source.to_string(), id))
+  }
+
+  pub fn is_blocked(&self, hash: Option<&str>) -> bool {
+    hash.is_some_and(|hash| self.blocked.contains(hash))
+  }
+
+  /// The strip without its blocked images, unless nothing of it may be shown
+  pub fn visible_strip(&self, source: &str, strip: &Arc<ComicStrip>) -> Option<Arc<ComicStrip>> {
+    if self.is_hidden(source, strip.id) {
+      return None;
+    }
+    if !strip
+      .comics
+      .iter()
+      .any(|comic| self.is_blocked(comic.image().blob_hash()))
+    {
+      return Some(strip.clone());
+    }
+
+    let comics: Vec<_> = strip
+      .comics
+      .iter()
+      .filter(|comic| !self.is_blocked(comic.image().blob_hash()))
+      .cloned()
+      .collect();
+    if comics.is_empty() {
+      return None;
+    }
+
+    Some(Arc::new(ComicStrip {
+      comics,
+      ..(**strip).clone()
+    }))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing;
+
+  #[test]
+  fn strips_are_moderated_per_source() {
+    testing::init();
+    let store = STORE.get();
+    let hidden = ModerationEntry::Hidden("feed:https://comic.example/feed".to_string(), 7);
+    let included = ModerationEntry::Included("directory:/comics".to_string(), 7);
+    store.save_moderation(&hidden).unwrap();
+    store.save_moderation(&included).unwrap();
+
+    let moderation = Moderation::load();
+    assert!(moderation.is_hidden("feed:https://comic.example/feed", 7));
+    assert!(!moderation.is_hidden("directory:/comics", 7));
+    assert!(moderation.is_included("directory:/comics", 7));
+    assert!(!moderation.is_included("feed:https://comic.example/feed", 7));
+
+    assert!(store.remove_moderation(&hidden).unwrap());
+    assert!(store.remove_moderation(&included).unwrap());
+    assert!(!Moderation::load().is_hidden("feed:https://comic.example/feed", 7));
+  }
+}
@@ -0,0 +1,830 @@
+use chrono::{DateTime, Utc};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::collection::ComicStrip;
+use crate::moderation::Moderation;
+use crate::source::{ComicSource, Popularity};
+use crate::{COLLECTION_ARC, STORE};
+
+const DEFAULT_HISTORY_SIZE: usize = 50;
+const DEFAULT_WEIGHTED_HISTORY_SIZE: usize = 1000;
+const DEFAULT_NO_REPEAT: usize = 20;
+const DEFAULT_HALF_LIFE_DAYS: f64 = 30.0;
+const DEFAULT_RECENCY_WEIGHT: f64 = 2.0;
+const DEFAULT_POPULARITY_WEIGHT: f64 = 2.0;
+const DEFAULT_UNSEEN_WEIGHT: f64 = 4.0;
+
+/// Strip of any collection, which may be shown.
+#[derive(Debug, Clone)]
+pub struct Candidate {
+  /// Identifier of the source, the strip belongs to
+  pub source: String,
+  pub strip: Arc<ComicStrip>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShownStrip {
+  pub source: String,
+  pub strip_id: u64,
+  pub shown_at: DateTime<Utc>,
+}
+
+/// Recently shown strips, the oldest first
+#[derive(Debug, Default)]
+pub struct History {
+  entries: VecDeque<ShownStrip>,
+}
+
+impl History {
+  pub fn new(entries: Vec<ShownStrip>) -> Self {
+    History {
+      entries: entries.into(),
+    }
+  }
+
+  // Position of the latest showing, higher positions are more recent
+  fn last_shown(&self, source: &str, strip_id: u64) -> Option<usize> {
+    self
+      .entries
+      .iter()
+      .rposition(|entry| entry.source == source && entry.strip_id == strip_id)
+  }
+
+  fn last_shown_source(&self, source: &str) -> Option<usize> {
+    self
+      .entries
+      .iter()
+      .rposition(|entry| entry.source == source)
+  }
+
+  // Whether the strip is among the latest `count` shown strips
+  fn is_recent(&self, source: &str, strip_id: u64, count: usize) -> bool {
+    self
+      .last_shown(source, strip_id)
+      .is_some_and(|position| position + count >= self.entries.len())
+  }
+
+  fn shown_count(&self, source: &str) -> usize {
+    self
+      .entries
+      .iter()
+      .filter(|entry| entry.source == source)
+      .count()
+  }
+
+  fn push(&mut self, entry: ShownStrip, size: usize) {
+    self.entries.push_back(entry);
+    while self.entries.len() > size {
+      self.entries.pop_front();
+    }
+  }
+}
+
+pub trait SelectionStrategy {
+  /// Order the candidates, the strips to show first, as of `now`. All
+  /// randomness is drawn from `rng`, so the order is reproducible by its seed.
+  fn order(
+    &self,
+    candidates: Vec<Candidate>,
+    history: &History,
+    now: DateTime<Utc>,
+    rng: &mut StdRng,
+  ) -> Vec<Candidate>;
+
+  /// Amount of shown strips the strategy needs to remember
+  fn history_size(&self) -> usize;
+}
+
+/// All strips of a single random source in random order, regardless of what
+/// has been shown before.
+pub struct RandomSelection;
+
+impl SelectionStrategy for RandomSelection {
+  fn order(
+    &self,
+    candidates: Vec<Candidate>,
+    _history: &History,
+    _now: DateTime<Utc>,
+    rng: &mut StdRng,
+  ) -> Vec<Candidate> {
+    let source = match candidates.choose(rng) {
+      Some(candidate) => candidate.source.clone(),
+      None => return vec![],
+    };
+
+    let mut ordered: Vec<Candidate> = candidates
+      .into_iter()
+      .filter(|candidate| candidate.source == source)
+      .collect();
+    ordered.shuffle(rng);
+    ordered
+  }
+
+  fn history_size(&self) -> usize {
+    0
+  }
+}
+
+/// Takes turns between the sources, so every source is shown about as often
+/// as its weight asks for. Strips shown within the last `history_size`
+/// selections are only repeated, once every other strip has been shown.
+pub struct FairRotation {
+  pub history_size: usize,
+  /// Weights of the sources by their identifier, defaulting to 1. Sources
+  /// without a positive weight are never shown.
+  pub weights: HashMap<String, f64>,
+}
+
+impl FairRotation {
+  fn weight(&self, source: &str) -> f64 {
+    self.weights.get(source).copied().unwrap_or(1.0)
+  }
+}
+
+impl SelectionStrategy for FairRotation {
+  fn order(
+    &self,
+    candidates: Vec<Candidate>,
+    history: &History,
+    _now: DateTime<Utc>,
+    rng: &mut StdRng,
+  ) -> Vec<Candidate> {
+    let (mut repeated, mut fresh): (Vec<Candidate>, Vec<Candidate>) = candidates
+      .into_iter()
+      .filter(|candidate| self.weight(&candidate.source) > 0.0)
+      .partition(|candidate| {
+        history
+          .last_shown(&candidate.source, candidate.strip.id)
+          .is_some()
+      });
+
+    // Sources with equal exposure take turns in random order
+    fresh.shuffle(rng);
+    let mut exposure: HashMap<String, f64> = HashMap::new();
+    for candidate in fresh.iter() {
+      exposure.entry(candidate.source.clone()).or_insert_with(|| {
+        history.shown_count(&candidate.source) as f64 / self.weight(&candidate.source)
+      });
+    }
+
+    let mut ordered = vec![];
+    while !fresh.is_empty() {
+      let source = fresh
+        .iter()
+        .map(|candidate| candidate.source.clone())
+        .min_by(|a, b| {
+          exposure[a].total_cmp(&exposure[b]).then(
+            history
+              .last_shown_source(a)
+              .cmp(&history.last_shown_source(b)),
+          )
+        })
+        .unwrap();
+
+      let indices: Vec<usize> = (0..fresh.len())
+        .filter(|index| fresh[*index].source == source)
+        .collect();
+      let index = *indices.choose(rng).unwrap();
+
+      *exposure.get_mut(&source).unwrap() += 1.0 / self.weight(&source);
+      ordered.push(fresh.swap_remove(index));
+    }
+
+    repeated.sort_by_key(|candidate| history.last_shown(&candidate.source, candidate.strip.id));
+    ordered.extend(repeated);
+    ordered
+  }
+
+  fn history_size(&self) -> usize {
+    self.history_size
+  }
+}
+
+/// Prefers new, popular and never shown strips. Every strip has a weight of
+/// one plus the configured weights of
+///
+/// - its age decay, halving every `half_life_days`,
+/// - its reactions relative to the most popular strip of the same source,
+/// - not being part of the history at all.
+///
+/// Strips are drawn by their weight, the latest `no_repeat` shown strips last.
+pub struct WeightedSelection {
+  pub history_size: usize,
+  pub no_repeat: usize,
+  pub half_life_days: f64,
+  pub recency: f64,
+  pub popularity: f64,
+  pub unseen: f64,
+  /// Weights of the sources by their identifier, scaling the weights of
+  /// their strips. Sources without a positive weight are never shown.
+  pub weights: HashMap<String, f64>,
+}
+
+// Reposts spread a strip further than likes
+fn popularity_score(popularity: Option<Popularity>) -> f64 {
+  popularity.map_or(0.0, |popularity| {
+    (1.0 + popularity.likes as f64 + 2.0 * popularity.reposts as f64).ln()
+  })
+}
+
+impl WeightedSelection {
+  fn weight(
+    &self,
+    candidate: &Candidate,
+    max_score: f64,
+    history: &History,
+    now: DateTime<Utc>,
+  ) -> f64 {
+    let age_days = (now - candidate.strip.created_at).num_seconds().max(0) as f64 / 86400.0;
+    let decay = match self.half_life_days > 0.0 {
+      true => 0.5_f64.powf(age_days / self.half_life_days),
+      false => 0.0,
+    };
+    let popularity = match max_score > 0.0 {
+      true => popularity_score(candidate.strip.popularity) / max_score,
+      false => 0.0,
+    };
+    let unseen = match history.last_shown(&candidate.source, candidate.strip.id) {
+      Some(_) => 0.0,
+      None => 1.0,
+    };
+
+    let source_weight = self.weights.get(&candidate.source).copied().unwrap_or(1.0);
+    source_weight
+      * (1.0 + self.recency * decay + self.popularity * popularity + self.unseen * unseen)
+  }
+}
+
+impl SelectionStrategy for WeightedSelection {
+  fn order(
+    &self,
+    candidates: Vec<Candidate>,
+    history: &History,
+    now: DateTime<Utc>,
+    rng: &mut StdRng,
+  ) -> Vec<Candidate> {
+    let mut max_scores: HashMap<String, f64> = HashMap::new();
+    for candidate in candidates.iter() {
+      let score = popularity_score(candidate.strip.popularity);
+      let max_score = max_scores.entry(candidate.source.clone()).or_insert(0.0);
+      *max_score = max_score.max(score);
+    }
+
+    // Weighted random order by sorting along u^(1/w) (Efraimidis-Spirakis)
+    let mut keyed: Vec<(f64, Candidate)> = candidates
+      .into_iter()
+      .filter_map(|candidate| {
+        let weight = self.weight(&candidate, max_scores[&candidate.source], history, now);
+        if weight <= 0.0 {
+          return None;
+        }
+        Some((rng.gen::<f64>().powf(1.0 / weight), candidate))
+      })
+      .collect();
+    keyed.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+
+    let (mut repeated, mut ordered): (Vec<Candidate>, Vec<Candidate>) = keyed
+      .into_iter()
+      .map(|(_, candidate)| candidate)
+      .partition(|candidate| {
+        history.is_recent(&candidate.source, candidate.strip.id, self.no_repeat)
+      });
+    repeated.sort_by_key(|candidate| history.last_shown(&candidate.source, candidate.strip.id));
+    ordered.extend(repeated);
+    ordered
+  }
+
+  fn history_size(&self) -> usize {
+    self.history_size
+  }
+}
+
+pub enum Selection {
+  Random(RandomSelection),
+  FairRotation(FairRotation),
+  Weighted(WeightedSelection),
+}
+
+impl Selection {
+  /// Order of the candidates drawn from the seed alone, regardless of the
+  /// history of any device. The same seed results in the same order of the
+  /// same candidates.
+  pub fn seeded_order(
+    &self,
+    candidates: Vec<Candidate>,
+    seed: u64,
+    now: DateTime<Utc>,
+  ) -> Vec<Candidate> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    self.order(candidates, &History::default(), now, &mut rng)
+  }
+}
+
+impl SelectionStrategy for Selection {
+  fn order(
+    &self,
+    candidates: Vec<Candidate>,
+    history: &History,
+    now: DateTime<Utc>,
+    rng: &mut StdRng,
+  ) -> Vec<Candidate> {
+    match self {
+      Selection::Random(ref selection) => selection.order(candidates, history, now, rng),
+      Selection::FairRotation(ref selection) => selection.order(candidates, history, now, rng),
+      Selection::Weighted(ref selection) => selection.order(candidates, history, now, rng),
+    }
+  }
+
+  fn history_size(&self) -> usize {
+    match self {
+      Selection::Random(ref selection) => selection.history_size(),
+      Selection::FairRotation(ref selection) => selection.history_size(),
+      Selection::Weighted(ref selection) => selection.history_size(),
+    }
+  }
+}
+
+impl From<RandomSelection> for Selection {
+  fn from(selection: RandomSelection) -> Self {
+    Self::Random(selection)
+  }
+}
+
+impl From<FairRotation> for Selection {
+  fn from(selection: FairRotation) -> Self {
+    Self::FairRotation(selection)
+  }
+}
+
+impl From<WeightedSelection> for Selection {
+  fn from(selection: WeightedSelection) -> Self {
+    Self::Weighted(selection)
+  }
+}
+
+/// Declarative description of the selection strategy, e.g.
+///
+/// `{"fair_rotation": {"history_size": 100, "weights": {"twitter:@islieb": 2}}}`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SelectionConfig {
+  Random {},
+  FairRotation {
+    history_size: Option<usize>,
+    weights: Option<HashMap<String, f64>>,
+  },
+  Weighted {
+    history_size: Option<usize>,
+    no_repeat: Option<usize>,
+    half_life_days: Option<f64>,
+    recency: Option<f64>,
+    popularity: Option<f64>,
+    unseen: Option<f64>,
+    weights: Option<HashMap<String, f64>>,
+  },
+}
+
+impl Default for SelectionConfig {
+  fn default() -> Self {
+    SelectionConfig::FairRotation {
+      history_size: None,
+      weights: None,
+    }
+  }
+}
+
+impl From<&SelectionConfig> for Selection {
+  fn from(config: &SelectionConfig) -> Self {
+    match config {
+      SelectionConfig::Random {} => Self::from(RandomSelection),
+      SelectionConfig::FairRotation {
+        history_size,
+        ref weights,
+      } => Self::from(FairRotation {
+        history_size: history_size.unwrap_or(DEFAULT_HISTORY_SIZE),
+        weights: weights.clone().unwrap_or_default(),
+      }),
+      SelectionConfig::Weighted {
+        history_size,
+        no_repeat,
+        half_life_days,
+        recency,
+        popularity,
+        unseen,
+        ref weights,
+      } => Self::from(WeightedSelection {
+        history_size: history_size.unwrap_or(DEFAULT_WEIGHTED_HISTORY_SIZE),
+        no_repeat: no_repeat.unwrap_or(DEFAULT_NO_REPEAT),
+        half_life_days: half_life_days.unwrap_or(DEFAULT_HALF_LIFE_DAYS),
+        recency: recency.unwrap_or(DEFAULT_RECENCY_WEIGHT),
+        popularity: popularity.unwrap_or(DEFAULT_POPULARITY_WEIGHT),
+        unseen: unseen.unwrap_or(DEFAULT_UNSEEN_WEIGHT),
+        weights: weights.clone().unwrap_or_default(),
+      }),
+    }
+  }
+}
+
+// Source and strips of every collection by its index, as of the last time it
+// was not locked
+type Snapshots = BTreeMap<usize, (String, Vec<Arc<ComicStrip>>)>;
+
+/// Picks the strips to show, remembering the strips shown before by every
+/// device on its own. Only the histories of the `max_devices` most recently
+/// used devices are kept.
+pub struct Selector {
+  strategy: Selection,
+  max_devices: usize,
+  /// Histories by device, the most recently used one last
+  histories: Mutex<Vec<(String, History)>>,
+  snapshots: Mutex<Snapshots>,
+}
+
+impl Selector {
+  /// Selector for the strips of `COLLECTION_ARC`, which has to be set before.
+  pub fn new(strategy: Selection, max_devices: usize) -> Self {
+    let selector = Selector {
+      strategy,
+      max_devices: max_devices.max(1),
+      histories: Mutex::new(vec![]),
+      snapshots: Mutex::new(BTreeMap::new()),
+    };
+    // Collections are not locked before their first refresh
+    drop(selector.snapshots());
+    selector
+  }
+
+  // Snapshots updated by all collections, which are not locked right now
+  fn snapshots(&self) -> MutexGuard<'_, Snapshots> {
+    let mut snapshots = self.snapshots.lock().unwrap();
+    for (index, collection_mut) in COLLECTION_ARC.get().iter().enumerate() {
+      if let Ok(collection) = collection_mut.try_lock() {
+        let snapshot = (
+          collection.source.identifier(),
+          collection.comic_strips.clone(),
+        );
+        snapshots.insert(index, snapshot);
+      }
+    }
+    snapshots
+  }
+
+  // History of the device, continuing with the one kept by the comic store
+  fn with_history<T>(&self, device: &str, f: impl FnOnce(&mut History) -> T) -> T {
+    let mut histories = self.histories.lock().unwrap();
+    let history = match histories.iter().position(|(name, _)| name == device) {
+      Some(index) => histories.remove(index),
+      None => {
+        let entries = STORE
+          .get()
+          .load_history(device, self.strategy.history_size())
+          .unwrap_or_else(|error| {
+            println!("Failed to restore selection history: {}", error);
+            vec![]
+          });
+        (device.to_string(), History::new(entries))
+      }
+    };
+    histories.push(history);
+    if histories.len() > self.max_devices {
+      let (forgotten, _) = histories.remove(0);
+      println!("Forgetting the history of {:?}", forgotten);
+    }
+    f(&mut histories.last_mut().unwrap().1)
+  }
+
+  // Strips of all collections, which may be shown. Refreshes keep a
+  // collection locked for minutes. Instead of waiting for them, the strips of
+  // the collection before the refresh are used.
+  fn candidates(&self) -> Vec<Candidate> {
+    let moderation = Moderation::load();
+    let mut candidates = vec![];
+    for (source, strips) in self.snapshots().values() {
+      for strip in strips.iter() {
+        if let Some(strip) = moderation.visible_strip(source, strip) {
+          candidates.push(Candidate {
+            source: source.clone(),
+            strip,
+          });
+        }
+      }
+    }
+    candidates
+  }
+
+  /// Strips, which may be shown, in the order they should be shown to the
+  /// device at `now`. The same seed results in the same order, as long as
+  /// neither the collections nor the history change.
+  pub fn comic_strips(&self, device: &str, seed: u64, now: DateTime<Utc>) -> Vec<Candidate> {
+    let candidates = self.candidates();
+    let mut rng = StdRng::seed_from_u64(seed);
+    self.with_history(device, |history| {
+      self.strategy.order(candidates, history, now, &mut rng)
+    })
+  }
+
+  /// Strips, which may be shown, in the order drawn from the seed alone.
+  pub fn seeded_strips(&self, seed: u64, now: DateTime<Utc>) -> Vec<Candidate> {
+    self.strategy.seeded_order(self.candidates(), seed, now)
+  }
+
+  /// Remember the strips, which have actually been shown to the device
+  pub fn mark_shown(&self, device: &str, shown: &[&Candidate], now: DateTime<Utc>) {
+    let size = self.strategy.history_size();
+    self.with_history(device, |history| {
+      for candidate in shown {
+        println!(
+          "Showing strip {} of {} on {:?}",
+          candidate.strip.id, candidate.source, device
+        );
+        let entry = ShownStrip {
+          source: candidate.source.clone(),
+          strip_id: candidate.strip.id,
+          shown_at: now,
+        };
+        if size == 0 {
+          continue;
+        }
+        if let Err(error) = STORE.get().save_shown(device, &entry, size) {
+          println!("Failed to save selection history: {}", error);
+        }
+        history.push(entry, size);
+      }
+    });
+    if size > 0 && !shown.is_empty() {
+      if let Err(error) = STORE.get().prune_histories(self.max_devices) {
+        println!("Failed to prune selection histories: {}", error);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::Duration;
+
+  use super::*;
+
+  fn candidate(source: &str, id: u64) -> Candidate {
+    Candidate {
+      source: source.to_string(),
+      strip: Arc::new(ComicStrip {
+        id,
+        comics: vec![],
+        created_at: Utc::now(),
+        author: None,
+        title: None,
+        popularity: None,
+      }),
+    }
+  }
+
+  fn history(shown: &[(&str, u64)]) -> History {
+    let start = Utc::now() - Duration::hours(shown.len() as i64);
+    History::new(
+      shown
+        .iter()
+        .enumerate()
+        .map(|(index, &(source, strip_id))| ShownStrip {
+          source: source.to_string(),
+          strip_id,
+          shown_at: start + Duration::hours(index as i64),
+        })
+        .collect(),
+    )
+  }
+
+  fn rotation(weights: &[(&str, f64)]) -> FairRotation {
+    FairRotation {
+      history_size: DEFAULT_HISTORY_SIZE,
+      weights: weights
+        .iter()
+        .map(|&(source, weight)| (source.to_string(), weight))
+        .collect(),
+    }
+  }
+
+  fn order(
+    strategy: &impl SelectionStrategy,
+    candidates: Vec<Candidate>,
+    history: &History,
+    seed: u64,
+  ) -> Vec<(String, u64)> {
+    strategy
+      .order(
+        candidates,
+        history,
+        Utc::now(),
+        &mut StdRng::seed_from_u64(seed),
+      )
+      .into_iter()
+      .map(|candidate| (candidate.source, candidate.strip.id))
+      .collect()
+  }
+
+  fn sources(ordered: &[(String, u64)]) -> Vec<&str> {
+    ordered.iter().map(|(source, _)| source.as_str()).collect()
+  }
+
+  #[test]
+  fn sources_take_turns() {
+    let candidates = vec![
+      candidate("a", 1),
+      candidate("a", 2),
+      candidate("a", 3),
+      candidate("b", 1),
+      candidate("b", 2),
+      candidate("b", 3),
+    ];
+    for seed in 0..10 {
+      let ordered = order(
+        &rotation(&[]),
+        candidates.clone(),
+        &History::default(),
+        seed,
+      );
+      assert_eq!(ordered.len(), 6);
+      let sources = sources(&ordered);
+      // Every round shows each source once
+      assert!(sources.chunks(2).all(|round| round[0] != round[1]));
+    }
+  }
+
+  #[test]
+  fn sources_are_shown_by_their_weight() {
+    let candidates = vec![
+      candidate("a", 1),
+      candidate("a", 2),
+      candidate("a", 3),
+      candidate("a", 4),
+      candidate("b", 1),
+      candidate("b", 2),
+      candidate("c", 1),
+    ];
+    let rotation = rotation(&[("a", 2.0), ("c", 0.0)]);
+    for seed in 0..10 {
+      let ordered = order(&rotation, candidates.clone(), &History::default(), seed);
+      assert_eq!(ordered.len(), 6);
+      let sources = sources(&ordered);
+      for prefix in [3, 6] {
+        let count = sources[..prefix]
+          .iter()
+          .filter(|source| **source == "a")
+          .count();
+        assert_eq!(count, prefix * 2 / 3);
+      }
+    }
+  }
+
+  #[test]
+  fn least_exposed_source_goes_first() {
+    let candidates = vec![candidate("a", 3), candidate("b", 3)];
+    let shown = history(&[("a", 1), ("b", 1), ("a", 2)]);
+    assert_eq!(
+      sources(&order(&rotation(&[]), candidates.clone(), &shown, 0)),
+      ["b", "a"]
+    );
+
+    // With equal exposure, the source shown longer ago goes first
+    let shown = history(&[("a", 1), ("b", 1)]);
+    assert_eq!(
+      sources(&order(&rotation(&[]), candidates, &shown, 0)),
+      ["a", "b"]
+    );
+  }
+
+  #[test]
+  fn shown_strips_are_repeated_last_the_longest_unseen_first() {
+    let candidates = vec![
+      candidate("a", 1),
+      candidate("a", 2),
+      candidate("a", 3),
+      candidate("b", 1),
+    ];
+    let shown = history(&[("a", 2), ("b", 1), ("a", 1)]);
+    let ordered = order(&rotation(&[]), candidates, &shown, 0);
+    assert_eq!(
+      ordered,
+      vec![
+        ("a".to_string(), 3),
+        ("a".to_string(), 2),
+        ("b".to_string(), 1),
+        ("a".to_string(), 1)
+      ]
+    );
+  }
+
+  #[test]
+  fn order_is_reproducible_by_its_seed() {
+    let candidates: Vec<Candidate> = (1..=20)
+      .map(|id| candidate(if id % 3 == 0 { "a" } else { "b" }, id))
+      .collect();
+    let rotation = rotation(&[]);
+    let first = order(&rotation, candidates.clone(), &History::default(), 7);
+    assert_eq!(first, order(&rotation, candidates, &History::default(), 7));
+  }
+
+  fn weighted(recency: f64, popularity: f64, unseen: f64) -> WeightedSelection {
+    WeightedSelection {
+      history_size: DEFAULT_WEIGHTED_HISTORY_SIZE,
+      no_repeat: 2,
+      half_life_days: 30.0,
+      recency,
+      popularity,
+      unseen,
+      weights: HashMap::new(),
+    }
+  }
+
+  fn aged(id: u64, age_days: i64, likes: Option<u64>) -> Candidate {
+    let mut candidate = candidate("a", id);
+    let strip = Arc::get_mut(&mut candidate.strip).unwrap();
+    strip.created_at = Utc::now() - Duration::days(age_days);
+    strip.popularity = likes.map(|likes| Popularity { likes, reposts: 0 });
+    candidate
+  }
+
+  // How often the strip is shown first out of 1000 seeds
+  fn first_count(
+    selection: &WeightedSelection,
+    candidates: &[Candidate],
+    history: &History,
+    id: u64,
+  ) -> usize {
+    (0..1000)
+      .filter(|seed| order(selection, candidates.to_vec(), history, *seed)[0].1 == id)
+      .count()
+  }
+
+  #[test]
+  fn weights_add_decay_popularity_and_unseen_terms() {
+    let selection = weighted(2.0, 2.0, 4.0);
+    let now = Utc::now();
+    let max_score = popularity_score(Some(Popularity {
+      likes: 99,
+      reposts: 0,
+    }));
+    let shown = history(&[("a", 2)]);
+
+    // Brand new, the most popular one and never shown
+    let weight = selection.weight(&aged(1, 0, Some(99)), max_score, &shown, now);
+    assert!((weight - 9.0).abs() < 1e-3);
+    // One half life old, without any reactions and shown before
+    let weight = selection.weight(&aged(2, 30, None), max_score, &shown, now);
+    assert!((weight - 2.0).abs() < 1e-3);
+
+    let mut selection = weighted(2.0, 2.0, 4.0);
+    selection.weights.insert("a".to_string(), 0.5);
+    let weight = selection.weight(&aged(2, 30, None), max_score, &shown, now);
+    assert!((weight - 1.0).abs() < 1e-3);
+  }
+
+  #[test]
+  fn new_popular_and_unseen_strips_are_preferred() {
+    let none = History::default();
+
+    // Weights of 3 against 1 result in being shown first 3 out of 4 times
+    let candidates = vec![aged(1, 0, Some(50)), aged(2, 0, None)];
+    let count = first_count(&weighted(0.0, 2.0, 0.0), &candidates, &none, 1);
+    assert!((700..800).contains(&count), "{}", count);
+
+    let candidates = vec![aged(1, 0, None), aged(2, 3650, None)];
+    let count = first_count(&weighted(2.0, 0.0, 0.0), &candidates, &none, 1);
+    assert!((700..800).contains(&count), "{}", count);
+
+    // Shown before, but not recently enough to be held back
+    let shown = history(&[("a", 2), ("a", 3), ("a", 4)]);
+    let candidates = vec![aged(1, 0, None), aged(2, 0, None)];
+    let count = first_count(&weighted(0.0, 0.0, 2.0), &candidates, &shown, 1);
+    assert!((700..800).contains(&count), "{}", count);
+
+    // Without any preference, both are shown first equally often
+    let count = first_count(&weighted(0.0, 0.0, 0.0), &candidates, &shown, 1);
+    assert!((450..550).contains(&count), "{}", count);
+  }
+
+  #[test]
+  fn latest_shown_strips_are_not_repeated() {
+    let selection = weighted(2.0, 2.0, 4.0);
+    let shown = history(&[("a", 1), ("a", 2), ("a", 3)]);
+    let candidates = vec![
+      aged(1, 300, None),
+      aged(2, 0, Some(1000)),
+      aged(3, 0, Some(1000)),
+      aged(4, 300, None),
+    ];
+    for seed in 0..20 {
+      let ids: Vec<u64> = order(&selection, candidates.clone(), &shown, seed)
+        .into_iter()
+        .map(|(_, id)| id)
+        .collect();
+      assert_eq!(ids.len(), 4);
+      assert_eq!(ids[2..], [2, 3]);
+    }
+  }
+}
@@ -0,0 +1,262 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+use crate::directory::DirectorySource;
+use crate::feed::FeedSource;
+use crate::http;
+use crate::manual::ManualSource;
+use crate::mastodon::MastodonSource;
+use crate::twitter::TwitterSource;
+
+// FNV-1a, as some sources identify their strips by arbitrary strings, but
+// strips need a numeric id, which must stay stable between releases.
+pub fn stable_id(value: &str) -> u64 {
+  let mut hash: u64 = 0xcbf29ce484222325;
+  for byte in value.bytes() {
+    hash ^= byte as u64;
+    hash = hash.wrapping_mul(0x100000001b3);
+  }
+  hash
+}
+
+pub async fn fetch_image(url: &str) -> Result<Vec<u8>, SourceError> {
+  println!(" -> {}", url);
+  http::download_image(url).await
+}
+
+/// Reactions to a post, as far as the source reports them.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Popularity {
+  pub likes: u64,
+  /// Retweets or boosts
+  pub reposts: u64,
+}
+
+/// A single post of a comic source, which may contain one or more images.
+///
+/// The images are only referenced by url at this point. Fetching and
+/// filtering them is the responsibility of the collection refresh.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceStrip {
+  pub id: u64,
+  pub created_at: DateTime<Utc>,
+  pub image_urls: Vec<String>,
+  #[serde(default)]
+  pub popularity: Option<Popularity>,
+}
+
+/// Result of one fetch against a comic source.
+#[derive(Debug, Clone)]
+pub struct SourceBatch {
+  pub strips: Vec<SourceStrip>,
+  /// Newest id seen by the source, which is handed back on the next fetch.
+  pub max_id: Option<u64>,
+  /// The batch contains every strip currently available at the source.
+  /// Strips of the collection, which are missing from it, have been removed.
+  pub exhaustive: bool,
+}
+
+#[derive(Debug)]
+pub enum SourceError {
+  Twitter(egg_mode::error::Error),
+  Http(reqwest::Error),
+  Feed(feed_rs::parser::ParseFeedError),
+  Io(std::io::Error),
+  Image(image::ImageError),
+  InvalidResponse(String),
+}
+
+impl fmt::Display for SourceError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      SourceError::Twitter(ref error) => write!(f, "twitter: {}", error),
+      SourceError::Http(ref error) => write!(f, "http: {}", error),
+      SourceError::Feed(ref error) => write!(f, "feed: {}", error),
+      SourceError::Io(ref error) => write!(f, "io: {}", error),
+      SourceError::Image(ref error) => write!(f, "image: {}", error),
+      SourceError::InvalidResponse(ref reason) => write!(f, "invalid response: {}", reason),
+    }
+  }
+}
+
+impl SourceError {
+  /// Whether retrying the failed operation later on might succeed
+  pub fn is_transient(&self) -> bool {
+    match self {
+      SourceError::Twitter(_) | SourceError::Io(_) => true,
+      SourceError::Http(ref error) => error.status().is_none_or(|status| status.is_server_error()),
+      SourceError::Feed(_) | SourceError::Image(_) | SourceError::InvalidResponse(_) => false,
+    }
+  }
+}
+
+impl std::error::Error for SourceError {}
+
+impl From<egg_mode::error::Error> for SourceError {
+  fn from(error: egg_mode::error::Error) -> Self {
+    Self::Twitter(error)
+  }
+}
+
+impl From<reqwest::Error> for SourceError {
+  fn from(error: reqwest::Error) -> Self {
+    Self::Http(error)
+  }
+}
+
+impl From<feed_rs::parser::ParseFeedError> for SourceError {
+  fn from(error: feed_rs::parser::ParseFeedError) -> Self {
+    Self::Feed(error)
+  }
+}
+
+impl From<std::io::Error> for SourceError {
+  fn from(error: std::io::Error) -> Self {
+    Self::Io(error)
+  }
+}
+
+impl From<image::ImageError> for SourceError {
+  fn from(error: image::ImageError) -> Self {
+    Self::Image(error)
+  }
+}
+
+#[async_trait]
+pub trait ComicSource: Send + Sync {
+  /// Human readable identification of the source, used for logging.
+  fn identifier(&self) -> String;
+
+  /// Fetch all strips newer than the given `max_id`.
+  async fn fetch_strips(&self, max_id: Option<u64>) -> Result<SourceBatch, SourceError>;
+
+  /// Load the raw data of one of the images referenced by a `SourceStrip`.
+  async fn load_image(&self, url: &str) -> Result<Vec<u8>, SourceError> {
+    fetch_image(url).await
+  }
+
+  /// Current reactions to the strips with the given ids. Strips, which are
+  /// no longer available, are missing from the result, as well as every
+  /// strip of sources without any reactions.
+  async fn fetch_popularity(&self, _ids: &[u64]) -> Result<Vec<(u64, Popularity)>, SourceError> {
+    Ok(vec![])
+  }
+
+  /// Whether strips of the source have to pass the filter. Otherwise they
+  /// are not filtered again, once the filter changes.
+  fn is_filtered(&self) -> bool {
+    true
+  }
+
+  /// Notification triggered by the source itself, whenever its content
+  /// changed. Sources, which can only be polled, do not provide one.
+  fn change_notifier(&self) -> Option<Arc<Notify>> {
+    None
+  }
+}
+
+#[derive(Clone)]
+pub enum Source {
+  Twitter(TwitterSource),
+  Feed(FeedSource),
+  Mastodon(MastodonSource),
+  Directory(DirectorySource),
+  Manual(ManualSource),
+}
+
+#[async_trait]
+impl ComicSource for Source {
+  fn identifier(&self) -> String {
+    match self {
+      Source::Twitter(ref source) => source.identifier(),
+      Source::Feed(ref source) => source.identifier(),
+      Source::Mastodon(ref source) => source.identifier(),
+      Source::Directory(ref source) => source.identifier(),
+      Source::Manual(ref source) => source.identifier(),
+    }
+  }
+
+  async fn fetch_strips(&self, max_id: Option<u64>) -> Result<SourceBatch, SourceError> {
+    match self {
+      Source::Twitter(ref source) => source.fetch_strips(max_id).await,
+      Source::Feed(ref source) => source.fetch_strips(max_id).await,
+      Source::Mastodon(ref source) => source.fetch_strips(max_id).await,
+      Source::Directory(ref source) => source.fetch_strips(max_id).await,
+      Source::Manual(ref source) => source.fetch_strips(max_id).await,
+    }
+  }
+
+  async fn load_image(&self, url: &str) -> Result<Vec<u8>, SourceError> {
+    match self {
+      Source::Twitter(ref source) => source.load_image(url).await,
+      Source::Feed(ref source) => source.load_image(url).await,
+      Source::Mastodon(ref source) => source.load_image(url).await,
+      Source::Directory(ref source) => source.load_image(url).await,
+      Source::Manual(ref source) => source.load_image(url).await,
+    }
+  }
+
+  async fn fetch_popularity(&self, ids: &[u64]) -> Result<Vec<(u64, Popularity)>, SourceError> {
+    match self {
+      Source::Twitter(ref source) => source.fetch_popularity(ids).await,
+      Source::Feed(ref source) => source.fetch_popularity(ids).await,
+      Source::Mastodon(ref source) => source.fetch_popularity(ids).await,
+      Source::Directory(ref source) => source.fetch_popularity(ids).await,
+      Source::Manual(ref source) => source.fetch_popularity(ids).await,
+    }
+  }
+
+  fn is_filtered(&self) -> bool {
+    match self {
+      Source::Twitter(ref source) => source.is_filtered(),
+      Source::Feed(ref source) => source.is_filtered(),
+      Source::Mastodon(ref source) => source.is_filtered(),
+      Source::Directory(ref source) => source.is_filtered(),
+      Source::Manual(ref source) => source.is_filtered(),
+    }
+  }
+
+  fn change_notifier(&self) -> Option<Arc<Notify>> {
+    match self {
+      Source::Twitter(ref source) => source.change_notifier(),
+      Source::Feed(ref source) => source.change_notifier(),
+      Source::Mastodon(ref source) => source.change_notifier(),
+      Source::Directory(ref source) => source.change_notifier(),
+      Source::Manual(ref source) => source.change_notifier(),
+    }
+  }
+}
+
+impl From<TwitterSource> for Source {
+  fn from(source: TwitterSource) -> Self {
+    Self::Twitter(source)
+  }
+}
+
+impl From<FeedSource> for Source {
+  fn from(source: FeedSource) -> Self {
+    Self::Feed(source)
+  }
+}
+
+impl From<MastodonSource> for Source {
+  fn from(source: MastodonSource) -> Self {
+    Self::Mastodon(source)
+  }
+}
+
+impl From<DirectorySource> for Source {
+  fn from(source: DirectorySource) -> Self {
+    Self::Directory(source)
+  }
+}
+
+impl From<ManualSource> for Source {
+  fn from(source: ManualSource) -> Self {
+    Self::Manual(source)
+  }
+}
@@ -0,0 +1,614 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::audit::{DecisionQuery, DecisionRecord};
+use crate::collection::{Comic, ComicStrip, PendingStrip};
+use crate::comic_image::ComicImage;
+use crate::frame::Frame;
+use crate::labeling::ImageLabel;
+use crate::moderation::{Moderation, ModerationEntry};
+use crate::selection::ShownStrip;
+use crate::source::{Popularity, SourceStrip};
+use crate::BLOBS;
+
+#[derive(Serialize, Deserialize)]
+struct StoredComic {
+  url: String,
+  hash: String,
+  width: u32,
+  height: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredStrip {
+  id: u64,
+  created_at: DateTime<Utc>,
+  author: Option<String>,
+  title: Option<String>,
+  comics: Vec<StoredComic>,
+  #[serde(default)]
+  popularity: Option<Popularity>,
+}
+
+/// On-disk storage of all collections, allowing to serve comics right after a
+/// restart without contacting any source.
+///
+/// Keys of strips are prefixed with the identifier of the source they belong
+/// to. Images are only referenced by the hash of their blob within the blob
+/// cache. Classification results are stored per strip and image url. Strips
+/// with rejected images are kept as well, to filter them again once the
+/// filter changes.
+/// Verdicts of cached filters are stored per image hash and filter identity.
+/// Manual moderation decisions are stored as keys without a value. The filter
+/// decision log is keyed by increasing ids. Manual labels for the training
+/// data are stored per image hash. The history of shown strips is kept per
+/// device, keyed like strips by the device and increasing ids. Frames and
+/// their images are keyed by increasing ids.
+pub struct ComicStore {
+  db: sled::Db,
+  strips: sled::Tree,
+  pending: sled::Tree,
+  rejected: sled::Tree,
+  classifications: sled::Tree,
+  verdicts: sled::Tree,
+  cursors: sled::Tree,
+  moderation: sled::Tree,
+  decisions: sled::Tree,
+  labels: sled::Tree,
+  history: sled::Tree,
+  frames: sled::Tree,
+  frame_images: sled::Tree,
+  meta: sled::Tree,
+}
+
+// Identifiers may contain slashes themselves (urls, paths), therefore a null
+// byte is used to separate them from the strip id.
+fn strip_prefix(identifier: &str) -> String {
+  format!("{}\0", identifier)
+}
+
+fn moderation_key(entry: &ModerationEntry) -> String {
+  match entry {
+    ModerationEntry::Hidden(source, id) => format!("hidden\0{}{}", strip_prefix(source), id),
+    ModerationEntry::Included(source, id) => {
+      format!("included\0{}{}", strip_prefix(source), id)
+    }
+    ModerationEntry::Blocked(hash) => format!("blocked\0{}", hash),
+  }
+}
+
+fn moderation_entry(key: &[u8]) -> Option<ModerationEntry> {
+  let key = std::str::from_utf8(key).ok()?;
+  let (kind, value) = key.split_once('\0')?;
+  let strip = || {
+    let (source, id) = value.rsplit_once('\0')?;
+    Some((source.to_string(), id.parse().ok()?))
+  };
+  match kind {
+    "hidden" => strip().map(|(source, id)| ModerationEntry::Hidden(source, id)),
+    "included" => strip().map(|(source, id)| ModerationEntry::Included(source, id)),
+    "blocked" => Some(ModerationEntry::Blocked(value.to_string())),
+    _ => None,
+  }
+}
+
+// Ids are zero padded to keep the natural order within the tree
+fn strip_key(identifier: &str, id: u64) -> String {
+  format!("{}{:020}", strip_prefix(identifier), id)
+}
+
+impl ComicStore {
+  pub fn open(path: &str) -> sled::Result<Self> {
+    let db = sled::open(path)?;
+
+    Ok(ComicStore {
+      strips: db.open_tree("strips")?,
+      pending: db.open_tree("pending")?,
+      rejected: db.open_tree("rejected")?,
+      classifications: db.open_tree("classifications")?,
+      verdicts: db.open_tree("verdicts")?,
+      cursors: db.open_tree("cursors")?,
+      moderation: db.open_tree("moderation")?,
+      decisions: db.open_tree("decisions")?,
+      labels: db.open_tree("labels")?,
+      history: db.open_tree("history")?,
+      frames: db.open_tree("frames")?,
+      frame_images: db.open_tree("frame_images")?,
+      meta: db.open_tree("meta")?,
+      db,
+    })
+  }
+
+  pub fn load_strips(&self, identifier: &str) -> sled::Result<Vec<Arc<ComicStrip>>> {
+    let mut strips = vec![];
+    for entry in self.strips.scan_prefix(strip_prefix(identifier)) {
+      let (_, value) = entry?;
+      let stored: StoredStrip = match serde_json::from_slice(&value) {
+        Ok(stored) => stored,
+        Err(error) => {
+          println!("Skipping unreadable stored strip: {}", error);
+          continue;
+        }
+      };
+
+      // Blobs may have been evicted in the meantime
+      let mut comics = vec![];
+      for comic in stored.comics {
+        if let Some(handle) = BLOBS.get().handle(&comic.hash) {
+          let image = ComicImage::from_blob(handle, comic.width, comic.height);
+          comics.push(Comic::new(comic.url, Arc::new(image)));
+        }
+      }
+
+      if !comics.is_empty() {
+        strips.push(Arc::new(ComicStrip {
+          id: stored.id,
+          comics,
+          created_at: stored.created_at,
+          author: stored.author,
+          title: stored.title,
+          popularity: stored.popularity,
+        }));
+      }
+    }
+
+    Ok(strips)
+  }
+
+  pub fn save_strip(&self, identifier: &str, strip: &ComicStrip) -> sled::Result<()> {
+    let comics = strip
+      .comics
+      .iter()
+      .filter_map(|comic| {
+        let image = comic.image();
+        Some(StoredComic {
+          url: comic.url.clone(),
+          hash: image.blob_hash()?.to_string(),
+          width: image.width(),
+          height: image.height(),
+        })
+      })
+      .collect();
+    let stored = StoredStrip {
+      id: strip.id,
+      created_at: strip.created_at,
+      author: strip.author.clone(),
+      title: strip.title.clone(),
+      comics,
+      popularity: strip.popularity,
+    };
+
+    self.strips.insert(
+      strip_key(identifier, strip.id),
+      serde_json::to_vec(&stored).unwrap(),
+    )?;
+
+    Ok(())
+  }
+
+  pub fn remove_strip(&self, identifier: &str, id: u64) -> sled::Result<()> {
+    self.strips.remove(strip_key(identifier, id))?;
+    Ok(())
+  }
+
+  pub fn load_pending(&self, identifier: &str) -> sled::Result<Vec<PendingStrip>> {
+    Ok(match self.pending.get(identifier)? {
+      Some(value) => serde_json::from_slice(&value).unwrap_or_else(|error| {
+        println!("Skipping unreadable pending strips: {}", error);
+        vec![]
+      }),
+      None => vec![],
+    })
+  }
+
+  pub fn save_pending(&self, identifier: &str, pending: &[PendingStrip]) -> sled::Result<()> {
+    self
+      .pending
+      .insert(identifier, serde_json::to_vec(pending).unwrap())?;
+    Ok(())
+  }
+
+  pub fn load_rejected(&self, identifier: &str) -> sled::Result<Vec<SourceStrip>> {
+    let mut rejected = vec![];
+    for entry in self.rejected.scan_prefix(strip_prefix(identifier)) {
+      let (_, value) = entry?;
+      match serde_json::from_slice(&value) {
+        Ok(strip) => rejected.push(strip),
+        Err(error) => println!("Skipping unreadable rejected strip: {}", error),
+      }
+    }
+    Ok(rejected)
+  }
+
+  pub fn save_rejected(&self, identifier: &str, strip: &SourceStrip) -> sled::Result<()> {
+    self.rejected.insert(
+      strip_key(identifier, strip.id),
+      serde_json::to_vec(strip).unwrap(),
+    )?;
+    Ok(())
+  }
+
+  pub fn remove_rejected(&self, identifier: &str, id: u64) -> sled::Result<()> {
+    self.rejected.remove(strip_key(identifier, id))?;
+    Ok(())
+  }
+
+  /// Identity of the filter, which the stored strips have passed
+  pub fn filter_identity(&self) -> sled::Result<Option<String>> {
+    Ok(
+      self
+        .meta
+        .get("filter_identity")?
+        .map(|value| String::from_utf8_lossy(&value).to_string()),
+    )
+  }
+
+  pub fn save_filter_identity(&self, identity: &str) -> sled::Result<()> {
+    self.meta.insert("filter_identity", identity.as_bytes())?;
+    Ok(())
+  }
+
+  pub fn max_id(&self, identifier: &str) -> sled::Result<Option<u64>> {
+    Ok(self.cursors.get(identifier)?.map(|value| {
+      let mut bytes = [0u8; 8];
+      bytes.copy_from_slice(&value);
+      u64::from_be_bytes(bytes)
+    }))
+  }
+
+  pub fn save_max_id(&self, identifier: &str, max_id: u64) -> sled::Result<()> {
+    self.cursors.insert(identifier, &max_id.to_be_bytes())?;
+    Ok(())
+  }
+
+  // Classifications are bound to the strip as well, as some sources (e.g.
+  // directories) may deliver different images for the same url over time.
+  pub fn classification(&self, id: u64, url: &str) -> sled::Result<Option<bool>> {
+    let key = format!("{}\0{}", id, url);
+    Ok(self.classifications.get(key)?.map(|value| value[..] == [1]))
+  }
+
+  pub fn save_classification(&self, id: u64, url: &str, valid: bool) -> sled::Result<()> {
+    let key = format!("{}\0{}", id, url);
+    self.classifications.insert(key, &[valid as u8])?;
+    Ok(())
+  }
+
+  pub fn clear_classifications(&self) -> sled::Result<()> {
+    self.classifications.clear()
+  }
+
+  pub fn verdict(&self, hash: &str, identity: &str) -> sled::Result<Option<bool>> {
+    let key = format!("{}\0{}", hash, identity);
+    Ok(self.verdicts.get(key)?.map(|value| value[..] == [1]))
+  }
+
+  pub fn save_verdict(&self, hash: &str, identity: &str, valid: bool) -> sled::Result<()> {
+    let key = format!("{}\0{}", hash, identity);
+    self.verdicts.insert(key, &[valid as u8])?;
+    Ok(())
+  }
+
+  /// Remove the verdicts of all filters for the given image hash
+  pub fn remove_verdicts(&self, hash: &str) -> sled::Result<usize> {
+    let mut removed = 0;
+    for entry in self.verdicts.scan_prefix(format!("{}\0", hash)) {
+      let (key, _) = entry?;
+      self.verdicts.remove(key)?;
+      removed += 1;
+    }
+    Ok(removed)
+  }
+
+  pub fn clear_verdicts(&self) -> sled::Result<usize> {
+    let removed = self.verdicts.len();
+    self.verdicts.clear()?;
+    Ok(removed)
+  }
+
+  pub fn moderation(&self) -> sled::Result<Moderation> {
+    let mut moderation = Moderation::default();
+    for entry in self.moderation.iter() {
+      let (key, _) = entry?;
+      match moderation_entry(&key) {
+        Some(entry) => moderation.insert(entry),
+        None => println!("Skipping unreadable moderation entry"),
+      }
+    }
+    Ok(moderation)
+  }
+
+  pub fn save_moderation(&self, entry: &ModerationEntry) -> sled::Result<()> {
+    self.moderation.insert(moderation_key(entry), &[])?;
+    Ok(())
+  }
+
+  /// Remove the decision, returning whether it existed at all
+  pub fn remove_moderation(&self, entry: &ModerationEntry) -> sled::Result<bool> {
+    Ok(self.moderation.remove(moderation_key(entry))?.is_some())
+  }
+
+  pub fn save_decision(&self, mut record: DecisionRecord, retention: Duration) -> sled::Result<()> {
+    record.id = self.db.generate_id()?;
+    self.decisions.insert(
+      record.id.to_be_bytes(),
+      serde_json::to_vec(&record).unwrap(),
+    )?;
+
+    // Ids increase over time, so the outdated records are the first ones
+    let cutoff = record.timestamp - retention;
+    for entry in self.decisions.iter() {
+      let (key, value) = entry?;
+      match serde_json::from_slice::<DecisionRecord>(&value) {
+        Ok(stored) if stored.timestamp >= cutoff => break,
+        _ => self.decisions.remove(key)?,
+      };
+    }
+
+    Ok(())
+  }
+
+  /// Records matching the query, newest first, starting below the given id
+  pub fn decisions(
+    &self,
+    query: &DecisionQuery,
+    before: Option<u64>,
+    limit: usize,
+  ) -> sled::Result<Vec<DecisionRecord>> {
+    let before = before.unwrap_or(u64::MAX).to_be_bytes();
+    let mut records = vec![];
+    for entry in self.decisions.range(..before).rev() {
+      if records.len() >= limit {
+        break;
+      }
+      let (_, value) = entry?;
+      match serde_json::from_slice::<DecisionRecord>(&value) {
+        Ok(record) if query.matches(&record) => records.push(record),
+        Ok(_) => {}
+        Err(error) => println!("Skipping unreadable decision record: {}", error),
+      }
+    }
+    Ok(records)
+  }
+
+  pub fn label(&self, hash: &str) -> sled::Result<Option<ImageLabel>> {
+    Ok(
+      self
+        .labels
+        .get(hash)?
+        .and_then(|value| serde_json::from_slice(&value).ok()),
+    )
+  }
+
+  pub fn labels(&self) -> sled::Result<Vec<(String, ImageLabel)>> {
+    let mut labels = vec![];
+    for entry in self.labels.iter() {
+      let (key, value) = entry?;
+      match serde_json::from_slice(&value) {
+        Ok(label) => labels.push((String::from_utf8_lossy(&key).to_string(), label)),
+        Err(error) => println!("Skipping unreadable label: {}", error),
+      }
+    }
+    Ok(labels)
+  }
+
+  pub fn save_label(&self, hash: &str, label: &ImageLabel) -> sled::Result<()> {
+    self
+      .labels
+      .insert(hash, serde_json::to_vec(label).unwrap())?;
+    Ok(())
+  }
+
+  /// Remove the label, returning whether the image was labeled at all
+  pub fn remove_label(&self, hash: &str) -> sled::Result<bool> {
+    Ok(self.labels.remove(hash)?.is_some())
+  }
+
+  /// The latest strips shown by the device, oldest first
+  pub fn load_history(&self, device: &str, limit: usize) -> sled::Result<Vec<ShownStrip>> {
+    let mut entries = vec![];
+    for entry in self
+      .history
+      .scan_prefix(strip_prefix(device))
+      .rev()
+      .take(limit)
+    {
+      let (_, value) = entry?;
+      match serde_json::from_slice(&value) {
+        Ok(shown) => entries.push(shown),
+        Err(error) => println!("Skipping unreadable history entry: {}", error),
+      }
+    }
+    entries.reverse();
+    Ok(entries)
+  }
+
+  /// Append the strip to the history of the device, keeping only its latest
+  /// `keep` entries
+  pub fn save_shown(&self, device: &str, shown: &ShownStrip, keep: usize) -> sled::Result<()> {
+    let id = self.db.generate_id()?;
+    self
+      .history
+      .insert(strip_key(device, id), serde_json::to_vec(shown).unwrap())?;
+
+    let keys = self
+      .history
+      .scan_prefix(strip_prefix(device))
+      .keys()
+      .collect::<sled::Result<Vec<_>>>()?;
+    for key in keys.iter().take(keys.len().saturating_sub(keep)) {
+      self.history.remove(key)?;
+    }
+    Ok(())
+  }
+
+  /// Remove the histories of all but the `keep` devices, which have been
+  /// shown strips most recently
+  pub fn prune_histories(&self, keep: usize) -> sled::Result<()> {
+    // Keys of every device along with the id of its latest entry
+    let mut devices: HashMap<String, (u64, Vec<sled::IVec>)> = HashMap::new();
+    for key in self.history.iter().keys() {
+      let key = key?;
+      let (device, id) = match std::str::from_utf8(&key)
+        .ok()
+        .and_then(|key| key.rsplit_once('\0'))
+        .and_then(|(device, id)| Some((device.to_string(), id.parse::<u64>().ok()?)))
+      {
+        Some(parsed) => parsed,
+        None => continue,
+      };
+      let (latest, keys) = devices.entry(device).or_insert((0, vec![]));
+      *latest = (*latest).max(id);
+      keys.push(key);
+    }
+
+    let mut devices: Vec<(u64, Vec<sled::IVec>)> = devices.into_values().collect();
+    devices.sort_by_key(|(latest, _)| std::cmp::Reverse(*latest));
+    for (_, keys) in devices.iter().skip(keep) {
+      for key in keys {
+        self.history.remove(key)?;
+      }
+    }
+    Ok(())
+  }
+
+  /// Save the frame along with its PNG image, returning its id. Only the
+  /// latest `keep` frames are kept.
+  pub fn save_frame(&self, mut frame: Frame, png: &[u8], keep: usize) -> sled::Result<u64> {
+    frame.id = self.db.generate_id()?;
+    let key = frame.id.to_be_bytes();
+    self.frame_images.insert(key, png)?;
+    self
+      .frames
+      .insert(key, serde_json::to_vec(&frame).unwrap())?;
+
+    while self.frames.len() > keep {
+      match self.frames.pop_min()? {
+        Some((key, _)) => self.frame_images.remove(key)?,
+        None => break,
+      };
+    }
+    Ok(frame.id)
+  }
+
+  pub fn frame(&self, id: u64) -> sled::Result<Option<(Frame, Vec<u8>)>> {
+    let key = id.to_be_bytes();
+    let frame = match self.frames.get(key)? {
+      Some(value) => match serde_json::from_slice(&value) {
+        Ok(frame) => frame,
+        Err(error) => {
+          println!("Skipping unreadable frame: {}", error);
+          return Ok(None);
+        }
+      },
+      None => return Ok(None),
+    };
+    Ok(self.frame_images.get(key)?.map(|png| (frame, png.to_vec())))
+  }
+
+  /// The latest frame created for the device
+  pub fn latest_frame(&self, device: &str) -> sled::Result<Option<Frame>> {
+    for entry in self.frames.iter().rev() {
+      let (_, value) = entry?;
+      if let Ok(frame) = serde_json::from_slice::<Frame>(&value) {
+        if frame.device == device {
+          return Ok(Some(frame));
+        }
+      }
+    }
+    Ok(None)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::frame::FRAME_HISTORY;
+  use crate::{testing, STORE};
+
+  fn shown(strip_id: u64) -> ShownStrip {
+    ShownStrip {
+      source: "fake:history".to_string(),
+      strip_id,
+      shown_at: Utc::now(),
+    }
+  }
+
+  #[test]
+  fn histories_of_the_least_recently_used_devices_are_pruned() {
+    testing::init();
+    let store = STORE.get();
+    for device in ["history-a", "history-b", "history-c"] {
+      store.save_shown(device, &shown(1), 10).unwrap();
+      store.save_shown(device, &shown(2), 10).unwrap();
+    }
+    // Showing another strip makes the first device the most recently used one
+    store.save_shown("history-a", &shown(3), 10).unwrap();
+
+    store.prune_histories(2).unwrap();
+    let strip_ids = |device| -> Vec<u64> {
+      store
+        .load_history(device, 10)
+        .unwrap()
+        .iter()
+        .map(|entry| entry.strip_id)
+        .collect()
+    };
+    assert_eq!(strip_ids("history-a"), vec![1, 2, 3]);
+    assert!(strip_ids("history-b").is_empty());
+    assert_eq!(strip_ids("history-c"), vec![1, 2]);
+  }
+
+  fn frame(device: &str, seed: u64) -> Frame {
+    Frame {
+      id: 0,
+      device: device.to_string(),
+      seed,
+      created_at: Utc::now(),
+      strips: vec![],
+    }
+  }
+
+  #[test]
+  fn frames_beyond_the_history_are_pruned() {
+    // Frames are pruned across all devices, so they get a store of their own
+    let path = std::env::temp_dir().join(format!(
+      "twitter_comic_streamer_frames_{}",
+      std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&path);
+    let store = ComicStore::open(path.to_str().unwrap()).unwrap();
+
+    let pruned = store
+      .save_frame(frame("frame-pruned", 0), b"pruned", FRAME_HISTORY)
+      .unwrap();
+    let mut ids = vec![];
+    for seed in 1..=150 {
+      let device = ["frame-a", "frame-b", "frame-c"][seed as usize % 3];
+      let png = format!("png {}", seed);
+      let id = store
+        .save_frame(frame(device, seed), png.as_bytes(), FRAME_HISTORY)
+        .unwrap();
+      ids.push(id);
+    }
+
+    assert_eq!(store.frames.len(), FRAME_HISTORY);
+    assert_eq!(store.frame_images.len(), FRAME_HISTORY);
+    assert!(store.frame(pruned).unwrap().is_none());
+    for id in &ids[..50] {
+      assert!(store.frame(*id).unwrap().is_none());
+    }
+    let (kept, png) = store.frame(ids[50]).unwrap().unwrap();
+    assert_eq!((kept.id, kept.seed), (ids[50], 51));
+    assert_eq!(png, b"png 51");
+
+    let latest = |device| store.latest_frame(device).unwrap().map(|frame| frame.seed);
+    assert_eq!(latest("frame-a"), Some(150));
+    assert_eq!(latest("frame-b"), Some(148));
+    assert_eq!(latest("frame-c"), Some(149));
+    assert_eq!(latest("frame-pruned"), None);
+    assert_eq!(store.latest_frame("frame-c").unwrap().unwrap().id, ids[148]);
+  }
+}
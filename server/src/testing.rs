@@ -0,0 +1,98 @@
+use image::{DynamicImage, ImageOutputFormat};
+use reqwest::Url;
+use std::path::PathBuf;
+use std::sync::{Arc, Once};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+use crate::blob_cache::BlobCache;
+use crate::http;
+use crate::store::ComicStore;
+use crate::{Config, BLOBS, CONFIG, HTTP, STORE};
+
+static INIT: Once = Once::new();
+
+/// Set up the configuration, the http client, the comic store and the blob
+/// cache within a temporary directory, once for all tests of the process.
+/// Tests share the store, so every test should use sources of its own.
+pub fn init() {
+  INIT.call_once(|| {
+    let directory =
+      std::env::temp_dir().join(format!("twitter_comic_streamer_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&directory);
+
+    let config: Config = serde_json::from_value(serde_json::json!({
+      "consumer_key": "",
+      "consumer_secret": "",
+      "access_token": "",
+      "access_token_secret": "",
+      "twitter_usernames": [],
+      "twitter_refresh_interval": 600,
+      "store_path": directory.join("comic_store"),
+      "blob_cache_path": directory.join("blob_cache"),
+    }))
+    .unwrap();
+
+    HTTP.set(http::build_client(config.http_connect_timeout, config.http_timeout).unwrap());
+    BLOBS.set(
+      BlobCache::open(
+        PathBuf::from(&config.blob_cache_path),
+        config.blob_cache_max_mib * 1024 * 1024,
+      )
+      .unwrap(),
+    );
+    STORE.set(ComicStore::open(&config.store_path).unwrap());
+    CONFIG.set(config);
+  });
+}
+
+/// Blank PNG encoded image of the given size
+pub fn png(width: u32, height: u32) -> Vec<u8> {
+  let mut data = vec![];
+  DynamicImage::new_rgb8(width, height)
+    .write_to(&mut data, ImageOutputFormat::Png)
+    .unwrap();
+  data
+}
+
+/// Local http server answering every request with the JSON returned by the
+/// handler for the requested url. Returns the base url of the server, which
+/// runs as long as the runtime of the test.
+pub async fn serve_json<F>(handler: F) -> String
+where
+  F: Fn(&Url) -> String + Send + Sync + 'static,
+{
+  let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+  let base_url = format!("http://{}", listener.local_addr().unwrap());
+  let handler = Arc::new(handler);
+
+  let server_url = base_url.clone();
+  tokio::spawn(async move {
+    while let Ok((mut stream, _)) = listener.accept().await {
+      let handler = handler.clone();
+      let server_url = server_url.clone();
+      tokio::spawn(async move {
+        let mut request = vec![];
+        let mut buffer = [0u8; 1024];
+        while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+          match stream.read(&mut buffer).await {
+            Ok(0) | Err(_) => return,
+            Ok(read) => request.extend_from_slice(&buffer[..read]),
+          }
+        }
+
+        let request = String::from_utf8_lossy(&request);
+        let target = request.split_whitespace().nth(1).unwrap_or("/");
+        let body = handler(&Url::parse(&format!("{}{}", server_url, target)).unwrap());
+        let response = format!(
+          "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+          body.len(),
+          body
+        );
+        let _ = stream.write_all(response.as_bytes()).await;
+      });
+    }
+  });
+
+  base_url
+}
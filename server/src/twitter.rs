@@ -1,19 +1,15 @@
-use chrono::DateTime;
+use async_trait::async_trait;
 use egg_mode::entities::MediaType;
-use egg_mode::tweet::Timeline;
+use egg_mode::tweet::{Timeline, Tweet};
 use egg_mode::user::UserID;
 use egg_mode::Token;
-use image::DynamicImage;
-use std::io::Cursor;
-use std::sync::Arc;
-use std::time::Duration;
-use tokio::sync::Mutex;
-use tokio::time::sleep;
-
-use crate::comic_image::ComicImage;
-use crate::filter::{Filter, HttpClassifierFilter, ImageFilter};
+
+use crate::source::{ComicSource, Popularity, SourceBatch, SourceError, SourceStrip};
 use crate::{CONFIG, TOKEN};
 
+// Maximum amount of tweets looked up at once
+const LOOKUP_SIZE: usize = 100;
+
 pub fn access_token() -> Token {
   let api_token = egg_mode::KeyPair::new(
     CONFIG.get().consumer_key.clone(),
@@ -31,164 +27,86 @@ pub fn access_token() -> Token {
 }
 
 fn user_timeline(user_id: UserID) -> Timeline {
-  egg_mode::tweet::user_timeline(user_id, false, false, &TOKEN.get())
-}
-
-#[derive(Debug, Clone)]
-pub struct Comic {
-  pub url: String,
-  image: Arc<ComicImage>,
+  egg_mode::tweet::user_timeline(user_id, false, false, TOKEN.get())
 }
 
-impl Comic {
-  pub fn new(url: String, image: Arc<ComicImage>) -> Self {
-    Comic { url, image }
-  }
-
-  pub fn image(&self) -> Arc<ComicImage> {
-    self.image.clone()
+fn popularity(tweet: &Tweet) -> Popularity {
+  Popularity {
+    likes: tweet.favorite_count.max(0) as u64,
+    reposts: tweet.retweet_count.max(0) as u64,
   }
 }
 
 #[derive(Debug, Clone)]
-pub struct ComicStrip {
-  pub id: u64,
-  pub comics: Vec<Comic>,
-  pub created_at: DateTime<chrono::Utc>,
-}
-
-#[derive(Clone)]
-pub struct UserComicCollection {
-  pub user_id: UserID,
-  pub comic_strips: Vec<Arc<ComicStrip>>,
-  max_id: Option<u64>,
-  pub max_amount: usize,
+pub struct TwitterSource {
+  user_id: UserID,
 }
 
-async fn fetch_image(url: String) -> DynamicImage {
-  println!(" -> {}", url);
-  let response = reqwest::get(url.as_str()).await.unwrap();
-  let in_bytes = response.bytes().await.unwrap();
-  let img = image::io::Reader::new(Cursor::new(in_bytes))
-    .with_guessed_format()
-    .unwrap()
-    .decode()
-    .unwrap();
-  img
+impl TwitterSource {
+  pub fn new(user_id: UserID) -> Self {
+    TwitterSource { user_id }
+  }
 }
 
-async fn refresh_user_comic_collection(collection: &UserComicCollection) -> UserComicCollection {
-  // TODO: Inject from the outside and make configurable
-  let filter = ImageFilter::from(HttpClassifierFilter::new(
-    CONFIG.get().http_classifier_url.clone(),
-  ));
-
-  let timeline = user_timeline(collection.user_id.clone())
-    //        .with_page_size(collection.max_amount as i32 * 3)
-    .with_page_size(200)
-    .older(collection.max_id);
-
-  let (timeline, feed) = timeline.await.unwrap();
+#[async_trait]
+impl ComicSource for TwitterSource {
+  fn identifier(&self) -> String {
+    match self.user_id {
+      UserID::ID(id) => format!("twitter:{}", id),
+      UserID::ScreenName(ref name) => format!("twitter:@{}", name),
+    }
+  }
 
-  let mut comic_strips = collection.comic_strips.clone();
-  let mut ids = collection.comic_ids();
+  async fn fetch_strips(&self, max_id: Option<u64>) -> Result<SourceBatch, SourceError> {
+    let timeline = user_timeline(self.user_id.clone())
+      //        .with_page_size(collection.max_amount as i32 * 3)
+      .with_page_size(200)
+      .older(max_id);
 
-  println!("Received {} tweets: processing...", feed.len());
+    let (timeline, feed) = timeline.await?;
 
-  for tweet in feed {
-    if ids.contains(&tweet.id) {
-      continue;
-    }
-    if let Some(media) = &tweet.entities.media {
-      let mut comics: Vec<Comic> = vec![];
-      for entry in media {
-        if entry.media_type != MediaType::Photo {
-          continue;
-        }
+    println!("Received {} tweets: processing...", feed.len());
 
-        if entry.expanded_url.contains("/video/") {
-          // Skip every entry, which expanded_url has a /video/ segment.
-          // Unfortunately video thumbnails are presented with "media_type" photo :(
-          continue;
-        }
+    let mut strips = vec![];
+    for tweet in feed.iter() {
+      if let Some(media) = &tweet.entities.media {
+        let mut image_urls = vec![];
+        for entry in media {
+          if entry.media_type != MediaType::Photo {
+            continue;
+          }
 
-        let url = entry.media_url.clone();
-        let image = Arc::new(ComicImage::from(fetch_image(url.clone()).await));
+          if entry.expanded_url.contains("/video/") {
+            // Skip every entry, which expanded_url has a /video/ segment.
+            // Unfortunately video thumbnails are presented with "media_type" photo :(
+            continue;
+          }
 
-        if filter.is_valid(image.clone()).await {
-          comics.push(Comic::new(url.clone(), image));
+          image_urls.push(entry.media_url.clone());
         }
-      }
 
-      if comics.len() > 0 {
-        comic_strips.push(Arc::new(ComicStrip {
+        strips.push(SourceStrip {
           id: tweet.id,
           created_at: tweet.created_at,
-          comics,
-        }));
+          image_urls,
+          popularity: Some(popularity(tweet)),
+        });
       }
-
-      // Mark tweet as being processed.
-      ids.push(tweet.id);
     }
-  }
 
-  let new_max_id = match timeline.max_id {
-    Some(max_id) => Some(max_id),
-    None => collection.max_id,
-  };
-
-  apply_collection_constraints(UserComicCollection {
-    user_id: collection.user_id.clone(),
-    max_id: new_max_id,
-    max_amount: collection.max_amount,
-    comic_strips,
-  })
-}
-
-fn apply_collection_constraints(mut collection: UserComicCollection) -> UserComicCollection {
-  collection
-    .comic_strips
-    .sort_by_key(|comic| comic.created_at);
-  collection.comic_strips = collection
-    .comic_strips
-    .into_iter()
-    .rev()
-    .take(collection.max_amount)
-    .collect();
-
-  collection
-}
-
-impl UserComicCollection {
-  pub fn new(user_id: UserID) -> Self {
-    Self::new_with_max_amount(user_id, 100)
+    Ok(SourceBatch {
+      strips,
+      max_id: timeline.max_id,
+      exhaustive: false,
+    })
   }
 
-  pub fn new_with_max_amount(user_id: UserID, max_amount: usize) -> Self {
-    UserComicCollection {
-      user_id,
-      max_amount,
-      max_id: None,
-      comic_strips: vec![],
+  async fn fetch_popularity(&self, ids: &[u64]) -> Result<Vec<(u64, Popularity)>, SourceError> {
+    let mut popularities = vec![];
+    for chunk in ids.chunks(LOOKUP_SIZE) {
+      let tweets = egg_mode::tweet::lookup(chunk.to_vec(), TOKEN.get()).await?;
+      popularities.extend(tweets.iter().map(|tweet| (tweet.id, popularity(tweet))));
     }
-  }
-
-  fn comic_ids(&self) -> Vec<u64> {
-    self.comic_strips.iter().map(|comic| comic.id).collect()
-  }
-}
-
-pub async fn twitter_refresh_task(collections: Arc<Vec<Mutex<UserComicCollection>>>) {
-  loop {
-    println!("Refreshing...");
-
-    for collection_mut in (*collections).iter() {
-      let mut collection = collection_mut.lock().await;
-      println!("Loading images for: {:?}", collection.user_id);
-      *collection = refresh_user_comic_collection(&collection).await;
-    }
-
-    sleep(Duration::from_secs(CONFIG.get().twitter_refresh_interval)).await;
+    Ok(popularities)
   }
 }
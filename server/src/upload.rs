@@ -0,0 +1,126 @@
+use chrono::Utc;
+use image::ImageError;
+use rocket::data::ToByteUnit;
+use rocket::form::{self, DataField, FromFormField};
+use rocket::http::Status;
+use rocket::response::status;
+use std::sync::Arc;
+
+use crate::audit::record_decision;
+use crate::collection::{Comic, ComicStrip};
+use crate::comic_image::ComicImage;
+use crate::filter::{Filter, ImageFilter, Verdict};
+use crate::manual::ManualSource;
+use crate::source::{stable_id, ComicSource, Source};
+use crate::COLLECTION_ARC;
+
+pub const UPLOAD_LIMIT_MIB: usize = 20;
+
+pub struct UploadedImage(pub Vec<u8>);
+
+#[rocket::async_trait]
+impl<'v> FromFormField<'v> for UploadedImage {
+  async fn from_data(field: DataField<'v, '_>) -> form::Result<'v, Self> {
+    let bytes = field
+      .data
+      .open(UPLOAD_LIMIT_MIB.mebibytes())
+      .into_bytes()
+      .await?;
+    if !bytes.is_complete() {
+      return Err(form::Error::validation("image exceeds the upload limit").into());
+    }
+
+    Ok(UploadedImage(bytes.into_inner()))
+  }
+}
+
+#[derive(rocket::FromForm)]
+pub struct ComicUpload {
+  pub image: UploadedImage,
+  pub author: Option<String>,
+  pub title: Option<String>,
+  pub url: Option<String>,
+  pub filter: Option<bool>,
+}
+
+pub async fn add_manual_strip(
+  bytes: Vec<u8>,
+  author: Option<String>,
+  title: Option<String>,
+  url: Option<String>,
+  filter: Option<&ImageFilter>,
+) -> Result<status::Custom<String>, status::Custom<String>> {
+  let image = match ComicImage::cached(&bytes) {
+    Ok(image) => Arc::new(image),
+    Err(ImageError::IoError(error)) => {
+      return Err(status::Custom(
+        Status::InternalServerError,
+        format!("Could not store uploaded image: {}", error),
+      ))
+    }
+    Err(_) => {
+      return Err(status::Custom(
+        Status::BadRequest,
+        "Uploaded data is not a supported image".to_string(),
+      ))
+    }
+  };
+
+  // Identified by their content, so uploading an image twice does not result
+  // in another strip.
+  let created_at = Utc::now();
+  let id = stable_id(&image.content_hash());
+  let url = url.unwrap_or_else(|| format!("upload:{}", id));
+
+  if let Some(image_filter) = filter {
+    let decision = image_filter.decision(image.clone()).await;
+    record_decision(
+      &ManualSource.identifier(),
+      id,
+      &url,
+      image.blob_hash(),
+      &decision,
+    );
+    match decision.verdict {
+      Verdict::Accept => {}
+      Verdict::Reject => {
+        return Err(status::Custom(
+          Status::UnprocessableEntity,
+          "Uploaded image has been rejected by the filter".to_string(),
+        ))
+      }
+      Verdict::Undecided => {
+        return Err(status::Custom(
+          Status::ServiceUnavailable,
+          "Uploaded image could not be judged by the filter, try again later".to_string(),
+        ))
+      }
+    }
+  }
+
+  let strip = Arc::new(ComicStrip {
+    id,
+    comics: vec![Comic::new(url, image)],
+    created_at,
+    author,
+    title,
+    popularity: None,
+  });
+
+  for collection_mut in COLLECTION_ARC.get().iter() {
+    let mut collection = collection_mut.lock().await;
+    if let Source::Manual(_) = collection.source {
+      if collection.comic_strips.iter().any(|strip| strip.id == id) {
+        return Ok(status::Custom(Status::Ok, id.to_string()));
+      }
+      println!("Adding uploaded strip {}", id);
+      collection.insert_strip(strip);
+      return Ok(status::Custom(Status::Created, id.to_string()));
+    }
+  }
+
+  Err(status::Custom(
+    Status::InternalServerError,
+    "No manual collection available".to_string(),
+  ))
+}